@@ -0,0 +1,994 @@
+use std::mem;
+
+use serde::{Deserialize, Serialize};
+
+/// the T-cycles per second of the DMG
+pub const CPU_FREQUENCY: u32 = 4_194_304;
+pub const SAMPLE_RATE: u32 = 48_000;
+
+/// the high pass filter of the DMG removes the DC offset of the DACs,
+/// this is how much of the capacitor charge is left after a T-cycle
+const HIGH_PASS_CHARGE: f32 = 0.999_958;
+
+const DUTY_PATTERNS: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1],
+    [1, 0, 0, 0, 0, 0, 0, 1],
+    [1, 0, 0, 0, 0, 1, 1, 1],
+    [0, 1, 1, 1, 1, 1, 1, 0],
+];
+
+const NOISE_DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+/// turns the channel off when it runs out, clocked at 256 Hz
+#[derive(Serialize, Deserialize)]
+struct Length {
+    enabled: bool,
+    counter: u16,
+    maximum: u16,
+}
+
+impl Length {
+    fn new(maximum: u16) -> Self {
+        Length {
+            enabled: false,
+            counter: 0,
+            maximum,
+        }
+    }
+
+    fn load(&mut self, value: u8) {
+        self.counter = self.maximum - u16::from(value);
+    }
+
+    /// returns true when the counter runs out
+    fn clock(&mut self) -> bool {
+        if !self.enabled || self.counter == 0 {
+            return false;
+        }
+        self.counter -= 1;
+        self.counter == 0
+    }
+
+    fn trigger(&mut self) {
+        if self.counter == 0 {
+            self.counter = self.maximum;
+        }
+    }
+}
+
+/// changes the volume of a channel, clocked at 64 Hz
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    /// NRx2, the initial volume, the direction and the period
+    register: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    fn new() -> Self {
+        Envelope {
+            register: 0,
+            volume: 0,
+            timer: 0,
+        }
+    }
+
+    /// the DAC is off when the initial volume is 0 and the volume decreases
+    fn dac_enabled(&self) -> bool {
+        self.register & 0xf8 != 0
+    }
+
+    fn get_period(&self) -> u8 {
+        self.register & 0x07
+    }
+
+    fn trigger(&mut self) {
+        self.volume = self.register >> 4;
+        self.timer = self.get_period();
+    }
+
+    fn clock(&mut self) {
+        if self.get_period() == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer > 0 {
+            return;
+        }
+        self.timer = self.get_period();
+        if self.register & 0x08 != 0 && self.volume < 15 {
+            self.volume += 1;
+        } else if self.register & 0x08 == 0 && self.volume > 0 {
+            self.volume -= 1;
+        }
+    }
+}
+
+/// changes the frequency of channel 1, clocked at 128 Hz
+#[derive(Serialize, Deserialize)]
+struct Sweep {
+    /// NR10, the period, the direction and the shift
+    register: u8,
+    enabled: bool,
+    shadow: u16,
+    timer: u8,
+}
+
+impl Sweep {
+    fn new() -> Self {
+        Sweep {
+            register: 0,
+            enabled: false,
+            shadow: 0,
+            timer: 0,
+        }
+    }
+
+    fn get_period(&self) -> u8 {
+        (self.register >> 4) & 0x07
+    }
+
+    fn get_shift(&self) -> u8 {
+        self.register & 0x07
+    }
+
+    /// a period of 0 is treated as 8 by the timer
+    fn reload_timer(&mut self) {
+        self.timer = match self.get_period() {
+            0 => 8,
+            period => period,
+        };
+    }
+
+    fn calculate(&self) -> u16 {
+        let delta = self.shadow >> self.get_shift();
+        if self.register & 0x08 != 0 {
+            return self.shadow - delta;
+        }
+        self.shadow + delta
+    }
+}
+
+/// channel 1 and 2, channel 2 has no sweep so its sweep register stays 0
+#[derive(Serialize, Deserialize)]
+struct Square {
+    enabled: bool,
+    duty: u8,
+    length: Length,
+    envelope: Envelope,
+    sweep: Sweep,
+    frequency: u16,
+    timer: u32,
+    position: u8,
+}
+
+impl Square {
+    fn new() -> Self {
+        let mut square = Square {
+            enabled: false,
+            duty: 0,
+            length: Length::new(64),
+            envelope: Envelope::new(),
+            sweep: Sweep::new(),
+            frequency: 0,
+            timer: 0,
+            position: 0,
+        };
+        square.timer = square.get_period();
+        square
+    }
+
+    fn get_period(&self) -> u32 {
+        (2048 - u32::from(self.frequency)) * 4
+    }
+
+    fn tick(&mut self, mut cycles: u32) {
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.get_period();
+            self.position = (self.position + 1) % 8;
+        }
+        self.timer -= cycles;
+    }
+
+    fn get_output(&self) -> f32 {
+        if !self.envelope.dac_enabled() {
+            return 0.0;
+        }
+        let high = DUTY_PATTERNS[usize::from(self.duty)][usize::from(self.position)] == 1;
+        if self.enabled && high {
+            return dac(self.envelope.volume);
+        }
+        dac(0)
+    }
+
+    fn write_duty_length(&mut self, value: u8) {
+        self.duty = value >> 6;
+        self.length.load(value & 0x3f);
+    }
+
+    fn write_envelope(&mut self, value: u8) {
+        self.envelope.register = value;
+        if !self.envelope.dac_enabled() {
+            self.enabled = false;
+        }
+    }
+
+    fn write_frequency_low(&mut self, value: u8) {
+        self.frequency = (self.frequency & 0x0700) | u16::from(value);
+    }
+
+    /// bit 7 triggers the channel, bit 6 enables the length counter
+    fn write_control(&mut self, value: u8) {
+        self.frequency = (self.frequency & 0x00ff) | (u16::from(value & 0x07) << 8);
+        self.length.enabled = value & 0x40 != 0;
+        if value & 0x80 != 0 {
+            self.trigger();
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger();
+        self.timer = self.get_period();
+        self.envelope.trigger();
+        self.sweep.shadow = self.frequency;
+        self.sweep.reload_timer();
+        self.sweep.enabled = self.sweep.get_period() != 0 || self.sweep.get_shift() != 0;
+        if self.sweep.get_shift() != 0 && self.sweep.calculate() > 2047 {
+            self.enabled = false;
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    /// the new frequency is checked for an overflow twice, once before and once after it is used
+    fn clock_sweep(&mut self) {
+        self.sweep.timer = self.sweep.timer.saturating_sub(1);
+        if self.sweep.timer > 0 {
+            return;
+        }
+        self.sweep.reload_timer();
+        if !self.sweep.enabled || self.sweep.get_period() == 0 {
+            return;
+        }
+        let frequency = self.sweep.calculate();
+        if frequency > 2047 {
+            self.enabled = false;
+        } else if self.sweep.get_shift() != 0 {
+            self.frequency = frequency;
+            self.sweep.shadow = frequency;
+            if self.sweep.calculate() > 2047 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    fn get_control(&self) -> u8 {
+        u8::from(self.length.enabled) << 6
+    }
+}
+
+/// channel 3 plays the 32 4 bit samples of wave ram
+#[derive(Serialize, Deserialize)]
+struct Wave {
+    enabled: bool,
+    dac_enabled: bool,
+    length: Length,
+    /// NR32 bits 5-6, mute, 100%, 50% or 25%
+    volume_code: u8,
+    frequency: u16,
+    timer: u32,
+    position: u8,
+    ram: [u8; 0x10],
+}
+
+impl Wave {
+    fn new() -> Self {
+        let mut wave = Wave {
+            enabled: false,
+            dac_enabled: false,
+            length: Length::new(256),
+            volume_code: 0,
+            frequency: 0,
+            timer: 0,
+            position: 0,
+            ram: [0; 0x10],
+        };
+        wave.timer = wave.get_period();
+        wave
+    }
+
+    fn get_period(&self) -> u32 {
+        (2048 - u32::from(self.frequency)) * 2
+    }
+
+    fn tick(&mut self, mut cycles: u32) {
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.get_period();
+            self.position = (self.position + 1) % 32;
+        }
+        self.timer -= cycles;
+    }
+
+    /// the high nibble of every byte is played first
+    fn get_sample(&self) -> u8 {
+        let byte = self.ram[usize::from(self.position / 2)];
+        if self.position.is_multiple_of(2) {
+            return byte >> 4;
+        }
+        byte & 0x0f
+    }
+
+    fn get_output(&self) -> f32 {
+        if !self.dac_enabled {
+            return 0.0;
+        }
+        if !self.enabled || self.volume_code == 0 {
+            return dac(0);
+        }
+        dac(self.get_sample() >> (self.volume_code - 1))
+    }
+
+    fn write_dac(&mut self, value: u8) {
+        self.dac_enabled = value & 0x80 != 0;
+        if !self.dac_enabled {
+            self.enabled = false;
+        }
+    }
+
+    fn write_frequency_low(&mut self, value: u8) {
+        self.frequency = (self.frequency & 0x0700) | u16::from(value);
+    }
+
+    fn write_control(&mut self, value: u8) {
+        self.frequency = (self.frequency & 0x00ff) | (u16::from(value & 0x07) << 8);
+        self.length.enabled = value & 0x40 != 0;
+        if value & 0x80 != 0 {
+            self.enabled = self.dac_enabled;
+            self.length.trigger();
+            self.timer = self.get_period();
+            self.position = 0;
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+}
+
+/// channel 4 plays the output of a linear feedback shift register
+#[derive(Serialize, Deserialize)]
+struct Noise {
+    enabled: bool,
+    length: Length,
+    envelope: Envelope,
+    /// NR43, the shift, the width and the divisor
+    register: u8,
+    lfsr: u16,
+    timer: u32,
+}
+
+impl Noise {
+    fn new() -> Self {
+        let mut noise = Noise {
+            enabled: false,
+            length: Length::new(64),
+            envelope: Envelope::new(),
+            register: 0,
+            lfsr: 0x7fff,
+            timer: 0,
+        };
+        noise.timer = noise.get_period();
+        noise
+    }
+
+    fn get_period(&self) -> u32 {
+        NOISE_DIVISORS[usize::from(self.register & 0x07)] << (self.register >> 4)
+    }
+
+    fn tick(&mut self, mut cycles: u32) {
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.get_period();
+            self.shift();
+        }
+        self.timer -= cycles;
+    }
+
+    /// in 7 bit mode the feedback is also written to bit 6
+    fn shift(&mut self) {
+        let feedback = (self.lfsr ^ (self.lfsr >> 1)) & 0x01;
+        self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+        if self.register & 0x08 != 0 {
+            self.lfsr = (self.lfsr & !0x40) | (feedback << 6);
+        }
+    }
+
+    fn get_output(&self) -> f32 {
+        if !self.envelope.dac_enabled() {
+            return 0.0;
+        }
+        if self.enabled && self.lfsr & 0x01 == 0 {
+            return dac(self.envelope.volume);
+        }
+        dac(0)
+    }
+
+    fn write_envelope(&mut self, value: u8) {
+        self.envelope.register = value;
+        if !self.envelope.dac_enabled() {
+            self.enabled = false;
+        }
+    }
+
+    fn write_control(&mut self, value: u8) {
+        self.length.enabled = value & 0x40 != 0;
+        if value & 0x80 != 0 {
+            self.enabled = self.envelope.dac_enabled();
+            self.length.trigger();
+            self.timer = self.get_period();
+            self.envelope.trigger();
+            self.lfsr = 0x7fff;
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+}
+
+/// the DACs turn the digital value 0-15 into an analog value from -1 to 1
+fn dac(value: u8) -> f32 {
+    f32::from(value) / 7.5 - 1.0
+}
+
+/// averages the output of the apu over the T-cycles of every sample and removes the DC offset,
+/// the samples of all channels are interleaved
+struct Resampler {
+    sample_rate: u32,
+    /// counts up by the sample rate every T-cycle, a sample is taken when it reaches the cpu frequency
+    timer: u32,
+    sums: Vec<f32>,
+    sum_cycles: u32,
+    capacitors: Vec<f32>,
+    samples: Vec<i16>,
+}
+
+impl Resampler {
+    fn new(sample_rate: u32, channels: usize) -> Self {
+        Resampler {
+            sample_rate: sample_rate.clamp(1, CPU_FREQUENCY),
+            timer: 0,
+            sums: vec![0.0; channels],
+            sum_cycles: 0,
+            capacitors: vec![0.0; channels],
+            samples: Vec::new(),
+        }
+    }
+
+    fn get_cycles_until_sample(&self) -> u32 {
+        (CPU_FREQUENCY - self.timer).div_ceil(self.sample_rate)
+    }
+
+    /// cycles may not be more than get_cycles_until_sample
+    fn add(&mut self, values: &[f32], cycles: u32) {
+        for (sum, value) in self.sums.iter_mut().zip(values) {
+            *sum += value * cycles as f32;
+        }
+        self.sum_cycles += cycles;
+        self.timer += cycles * self.sample_rate;
+        if self.timer >= CPU_FREQUENCY {
+            self.timer -= CPU_FREQUENCY;
+            self.push_sample();
+        }
+    }
+
+    fn push_sample(&mut self) {
+        let charge = HIGH_PASS_CHARGE.powf(CPU_FREQUENCY as f32 / self.sample_rate as f32);
+        for (sum, capacitor) in self.sums.iter_mut().zip(self.capacitors.iter_mut()) {
+            let value = *sum / self.sum_cycles as f32;
+            let output = value - *capacitor;
+            *capacitor = value - output * charge;
+            self.samples.push(to_sample(output));
+            *sum = 0.0;
+        }
+        self.sum_cycles = 0;
+    }
+}
+
+/// records the mixed output and optionally every channel on its own
+pub struct Recorder {
+    mixed: Resampler,
+    channels: Option<Resampler>,
+}
+
+/// the samples of a finished recording
+pub struct Recording {
+    pub sample_rate: u32,
+    /// left and right interleaved
+    pub mixed: Vec<i16>,
+    /// a mono track for every channel before it is panned and scaled by the master volume
+    pub channels: Option<[Vec<i16>; 4]>,
+}
+
+/// the audio processing unit, produces interleaved stereo samples at the sample rate
+#[derive(Serialize, Deserialize)]
+pub struct Apu {
+    /// NR52 bit 7, all registers are cleared and read only while the apu is off
+    enabled: bool,
+    square1: Square,
+    square2: Square,
+    wave: Wave,
+    noise: Noise,
+    /// NR50, the left and right master volume
+    volume: u8,
+    /// NR51, which channels are played on the left and the right
+    panning: u8,
+    frame_step: u8,
+    #[serde(skip, default = "get_default_output")]
+    output: Resampler,
+    #[serde(skip)]
+    recorder: Option<Recorder>,
+}
+
+fn get_default_output() -> Resampler {
+    Resampler::new(SAMPLE_RATE, 2)
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Apu {
+            enabled: false,
+            square1: Square::new(),
+            square2: Square::new(),
+            wave: Wave::new(),
+            noise: Noise::new(),
+            volume: 0,
+            panning: 0,
+            frame_step: 0,
+            output: get_default_output(),
+            recorder: None,
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.output.sample_rate = sample_rate.clamp(1, CPU_FREQUENCY);
+    }
+
+    /// the samples produced since the last call, left and right interleaved
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        mem::take(&mut self.output.samples)
+    }
+
+    /// the output isn't part of the machine state, it is taken over when a state is loaded
+    pub fn keep_output(&mut self, previous: &mut Apu) {
+        self.output = mem::replace(&mut previous.output, get_default_output());
+        self.recorder = previous.recorder.take();
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    /// record at its own sample rate, independent of the rate of the audio device
+    pub fn start_recording(&mut self, sample_rate: u32, channels: bool) {
+        self.recorder = Some(Recorder {
+            mixed: Resampler::new(sample_rate, 2),
+            channels: channels.then(|| Resampler::new(sample_rate, 4)),
+        });
+    }
+
+    pub fn stop_recording(&mut self) -> Option<Recording> {
+        let recorder = self.recorder.take()?;
+        let channels = recorder.channels.map(|resampler| {
+            let mut channels: [Vec<i16>; 4] = Default::default();
+            for (index, sample) in resampler.samples.iter().enumerate() {
+                channels[index % 4].push(*sample);
+            }
+            channels
+        });
+        Some(Recording {
+            sample_rate: recorder.mixed.sample_rate,
+            mixed: recorder.mixed.samples,
+            channels,
+        })
+    }
+
+    /// advance the channels by the given amount of T-cycles,
+    /// every output sample is the average of the channel output over its T-cycles
+    pub fn tick(&mut self, cycles: usize) {
+        self.advance(cycles, true);
+    }
+
+    /// fill the output with silence while the system clock is stopped, the channels don't move
+    pub fn idle(&mut self, cycles: usize) {
+        self.advance(cycles, false);
+    }
+
+    fn advance(&mut self, cycles: usize, running: bool) {
+        let mut remaining = cycles as u32;
+        while remaining > 0 {
+            let step = remaining.min(self.get_cycles_until_sample());
+            if running && self.enabled {
+                self.square1.tick(step);
+                self.square2.tick(step);
+                self.wave.tick(step);
+                self.noise.tick(step);
+            }
+            let outputs = if running { self.get_channel_outputs() } else { [0.0; 4] };
+            let mixed = self.mix(&outputs);
+            self.output.add(&mixed, step);
+            if let Some(recorder) = &mut self.recorder {
+                recorder.mixed.add(&mixed, step);
+                if let Some(channels) = &mut recorder.channels {
+                    channels.add(&outputs, step);
+                }
+            }
+            remaining -= step;
+        }
+    }
+
+    /// the T-cycles until the output or the recorder takes the next sample
+    fn get_cycles_until_sample(&self) -> u32 {
+        let mut cycles = self.output.get_cycles_until_sample();
+        if let Some(recorder) = &self.recorder {
+            cycles = cycles.min(recorder.mixed.get_cycles_until_sample());
+            if let Some(channels) = &recorder.channels {
+                cycles = cycles.min(channels.get_cycles_until_sample());
+            }
+        }
+        cycles
+    }
+
+    /// clocked at 512 Hz by bit 4 of DIV falling
+    pub fn clock_frame_sequencer(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.frame_step.is_multiple_of(2) {
+            self.square1.clock_length();
+            self.square2.clock_length();
+            self.wave.clock_length();
+            self.noise.clock_length();
+        }
+        if self.frame_step == 2 || self.frame_step == 6 {
+            self.square1.clock_sweep();
+        }
+        if self.frame_step == 7 {
+            self.square1.envelope.clock();
+            self.square2.envelope.clock();
+            self.noise.envelope.clock();
+        }
+        self.frame_step = (self.frame_step + 1) % 8;
+    }
+
+    /// the bits that can't be read are set by the memory map
+    pub fn read(&self, location: usize) -> u8 {
+        match location {
+            0xff10 => self.square1.sweep.register,
+            0xff11 => self.square1.duty << 6,
+            0xff12 => self.square1.envelope.register,
+            0xff14 => self.square1.get_control(),
+            0xff16 => self.square2.duty << 6,
+            0xff17 => self.square2.envelope.register,
+            0xff19 => self.square2.get_control(),
+            0xff1a => u8::from(self.wave.dac_enabled) << 7,
+            0xff1c => self.wave.volume_code << 5,
+            0xff1e => u8::from(self.wave.length.enabled) << 6,
+            0xff21 => self.noise.envelope.register,
+            0xff22 => self.noise.register,
+            0xff23 => u8::from(self.noise.length.enabled) << 6,
+            0xff24 => self.volume,
+            0xff25 => self.panning,
+            0xff26 => self.get_status(),
+            0xff30..= 0xff3f => self.wave.ram[location - 0xff30],
+            _ => 0xff,
+        }
+    }
+
+    pub fn write(&mut self, location: usize, value: u8) {
+        match location {
+            0xff26 => return self.set_power(value & 0x80 != 0),
+            0xff30..= 0xff3f => return self.wave.ram[location - 0xff30] = value,
+            _ => (),
+        }
+        if !self.enabled {
+            // on the DMG the length counters can still be loaded while the apu is off
+            match location {
+                0xff11 => self.square1.length.load(value & 0x3f),
+                0xff16 => self.square2.length.load(value & 0x3f),
+                0xff1b => self.wave.length.load(value),
+                0xff20 => self.noise.length.load(value & 0x3f),
+                _ => (),
+            }
+            return;
+        }
+        match location {
+            0xff10 => self.square1.sweep.register = value & 0x7f,
+            0xff11 => self.square1.write_duty_length(value),
+            0xff12 => self.square1.write_envelope(value),
+            0xff13 => self.square1.write_frequency_low(value),
+            0xff14 => self.square1.write_control(value),
+            0xff16 => self.square2.write_duty_length(value),
+            0xff17 => self.square2.write_envelope(value),
+            0xff18 => self.square2.write_frequency_low(value),
+            0xff19 => self.square2.write_control(value),
+            0xff1a => self.wave.write_dac(value),
+            0xff1b => self.wave.length.load(value),
+            0xff1c => self.wave.volume_code = (value >> 5) & 0x03,
+            0xff1d => self.wave.write_frequency_low(value),
+            0xff1e => self.wave.write_control(value),
+            0xff20 => self.noise.length.load(value & 0x3f),
+            0xff21 => self.noise.write_envelope(value),
+            0xff22 => self.noise.register = value,
+            0xff23 => self.noise.write_control(value),
+            0xff24 => self.volume = value,
+            0xff25 => self.panning = value,
+            _ => (),
+        }
+    }
+
+    /// bit 7 is the power, bits 0-3 are set while channel 1-4 are playing
+    fn get_status(&self) -> u8 {
+        u8::from(self.enabled) << 7
+            | u8::from(self.noise.enabled) << 3
+            | u8::from(self.wave.enabled) << 2
+            | u8::from(self.square2.enabled) << 1
+            | u8::from(self.square1.enabled)
+    }
+
+    /// turning the apu off clears every register except wave ram and, on the DMG, the length counters
+    fn set_power(&mut self, enabled: bool) {
+        if self.enabled && !enabled {
+            let mut square1 = Square::new();
+            let mut square2 = Square::new();
+            let mut wave = Wave::new();
+            let mut noise = Noise::new();
+            square1.length.counter = self.square1.length.counter;
+            square2.length.counter = self.square2.length.counter;
+            wave.length.counter = self.wave.length.counter;
+            wave.ram = self.wave.ram;
+            noise.length.counter = self.noise.length.counter;
+            self.square1 = square1;
+            self.square2 = square2;
+            self.wave = wave;
+            self.noise = noise;
+            self.volume = 0;
+            self.panning = 0;
+        }
+        if !self.enabled && enabled {
+            self.frame_step = 0;
+        }
+        self.enabled = enabled;
+    }
+
+    fn get_channel_outputs(&self) -> [f32; 4] {
+        [
+            self.square1.get_output(),
+            self.square2.get_output(),
+            self.wave.get_output(),
+            self.noise.get_output(),
+        ]
+    }
+
+    /// the four channels panned by NR51 and scaled by the master volume, from -1 to 1
+    fn mix(&self, outputs: &[f32; 4]) -> [f32; 2] {
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for (index, output) in outputs.iter().enumerate() {
+            if self.panning & (0x10 << index) != 0 {
+                left += output;
+            }
+            if self.panning & (0x01 << index) != 0 {
+                right += output;
+            }
+        }
+        let left_volume = f32::from(((self.volume >> 4) & 0x07) + 1) / 8.0;
+        let right_volume = f32::from((self.volume & 0x07) + 1) / 8.0;
+        [left / 4.0 * left_volume, right / 4.0 * right_volume]
+    }
+}
+
+fn to_sample(value: f32) -> i16 {
+    (value.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_apu() -> Apu {
+        let mut apu = Apu::new();
+        apu.write(0xff26, 0x80);
+        apu.write(0xff24, 0x77);
+        apu.write(0xff25, 0xff);
+        apu
+    }
+
+    #[test]
+    fn test_sample_rate() -> Result<(), String> {
+        let mut apu = get_apu();
+        for _ in 0..CPU_FREQUENCY / 16 {
+            apu.tick(16);
+        }
+        assert_eq!(apu.take_samples().len(), SAMPLE_RATE as usize * 2);
+        assert!(apu.take_samples().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_recording() -> Result<(), String> {
+        let mut apu = get_apu();
+        apu.write(0xff25, 0x22);
+        apu.write(0xff16, 0x80);
+        apu.write(0xff17, 0xf0);
+        apu.write(0xff19, 0x87);
+        apu.start_recording(22_050, true);
+        for _ in 0..CPU_FREQUENCY / 16 {
+            apu.tick(16);
+        }
+        let recording = apu.stop_recording().ok_or("expected a recording")?;
+        assert!(!apu.is_recording());
+        assert_eq!(recording.sample_rate, 22_050);
+        assert_eq!(recording.mixed.len(), 22_050 * 2);
+        assert_eq!(apu.take_samples().len(), SAMPLE_RATE as usize * 2);
+        let channels = recording.channels.ok_or("expected channel recordings")?;
+        assert!(channels.iter().all(|channel| channel.len() == 22_050));
+        assert!(channels[1].iter().any(|sample| *sample > 1000));
+        assert!(channels[0].iter().all(|sample| *sample == 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_square() -> Result<(), String> {
+        let mut apu = get_apu();
+        apu.write(0xff16, 0x80);
+        apu.write(0xff17, 0xf0);
+        apu.write(0xff18, 0x00);
+        apu.write(0xff19, 0x87);
+        assert_eq!(apu.read(0xff26), 0x82);
+        for _ in 0..1000 {
+            apu.tick(16);
+        }
+        let samples = apu.take_samples();
+        assert!(samples.iter().any(|sample| *sample > 1000));
+        assert!(samples.iter().any(|sample| *sample < -1000));
+        // a DAC that is turned off turns the channel off
+        apu.write(0xff17, 0x00);
+        assert_eq!(apu.read(0xff26), 0x80);
+        Ok(())
+    }
+
+    #[test]
+    fn test_length() -> Result<(), String> {
+        let mut apu = get_apu();
+        apu.write(0xff12, 0xf0);
+        apu.write(0xff11, 0x3e);
+        apu.write(0xff14, 0xc0);
+        apu.clock_frame_sequencer();
+        assert_eq!(apu.read(0xff26) & 0x01, 0x01);
+        apu.clock_frame_sequencer();
+        apu.clock_frame_sequencer();
+        assert_eq!(apu.read(0xff26) & 0x01, 0x00);
+        // without the length enabled the channel keeps playing
+        apu.write(0xff14, 0x80);
+        for _ in 0..8 * 64 {
+            apu.clock_frame_sequencer();
+        }
+        assert_eq!(apu.read(0xff26) & 0x01, 0x01);
+        Ok(())
+    }
+
+    #[test]
+    fn test_envelope() -> Result<(), String> {
+        let mut apu = get_apu();
+        apu.write(0xff21, 0x21);
+        apu.write(0xff23, 0x80);
+        assert_eq!(apu.noise.envelope.volume, 2);
+        for _ in 0..8 {
+            apu.clock_frame_sequencer();
+        }
+        assert_eq!(apu.noise.envelope.volume, 1);
+        for _ in 0..16 {
+            apu.clock_frame_sequencer();
+        }
+        assert_eq!(apu.noise.envelope.volume, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sweep() -> Result<(), String> {
+        let mut apu = get_apu();
+        apu.write(0xff12, 0xf0);
+        apu.write(0xff10, 0x12);
+        apu.write(0xff13, 0x00);
+        apu.write(0xff14, 0x84);
+        // step 2 is the first one that clocks the sweep
+        for _ in 0..3 {
+            apu.clock_frame_sequencer();
+        }
+        assert_eq!(apu.square1.frequency, 0x500);
+        assert_eq!(apu.read(0xff26) & 0x01, 0x01);
+        for _ in 0..4 {
+            apu.clock_frame_sequencer();
+        }
+        assert_eq!(apu.square1.frequency, 0x640);
+        assert_eq!(apu.read(0xff26) & 0x01, 0x01);
+        // the frequency after the next one would overflow
+        for _ in 0..4 {
+            apu.clock_frame_sequencer();
+        }
+        assert_eq!(apu.square1.frequency, 0x7d0);
+        assert_eq!(apu.read(0xff26) & 0x01, 0x00);
+        // an overflow when triggering turns the channel off right away
+        apu.write(0xff13, 0xff);
+        apu.write(0xff14, 0x87);
+        assert_eq!(apu.read(0xff26) & 0x01, 0x00);
+        Ok(())
+    }
+
+    #[test]
+    fn test_wave() -> Result<(), String> {
+        let mut apu = get_apu();
+        apu.write(0xff30, 0x4f);
+        assert_eq!(apu.read(0xff30), 0x4f);
+        apu.write(0xff1a, 0x80);
+        apu.write(0xff1c, 0x20);
+        apu.write(0xff1e, 0x80);
+        assert_eq!(apu.read(0xff26), 0x84);
+        assert_eq!(apu.wave.get_output(), dac(0x04));
+        apu.write(0xff1c, 0x60);
+        assert_eq!(apu.wave.get_output(), dac(0x01));
+        apu.tick(2048 * 2);
+        assert_eq!(apu.wave.get_output(), dac(0x03));
+        Ok(())
+    }
+
+    #[test]
+    fn test_noise() -> Result<(), String> {
+        let mut apu = get_apu();
+        apu.write(0xff21, 0xf0);
+        apu.write(0xff22, 0x00);
+        apu.write(0xff23, 0x80);
+        apu.noise.shift();
+        assert_eq!(apu.noise.lfsr, 0x3fff);
+        // 7 bit mode
+        apu.write(0xff22, 0x08);
+        apu.noise.lfsr = 0x0001;
+        apu.noise.shift();
+        assert_eq!(apu.noise.lfsr, 0x4040);
+        apu.tick(8);
+        assert_eq!(apu.noise.lfsr, 0x2020);
+        Ok(())
+    }
+
+    #[test]
+    fn test_power() -> Result<(), String> {
+        let mut apu = get_apu();
+        apu.write(0xff30, 0x12);
+        apu.write(0xff12, 0xf0);
+        apu.write(0xff14, 0x80);
+        apu.write(0xff26, 0x00);
+        assert_eq!(apu.read(0xff26), 0x00);
+        assert_eq!(apu.read(0xff12), 0x00);
+        assert_eq!(apu.read(0xff25), 0x00);
+        assert_eq!(apu.read(0xff30), 0x12);
+        apu.write(0xff12, 0xf0);
+        assert_eq!(apu.read(0xff12), 0x00);
+        apu.write(0xff11, 0x3f);
+        assert_eq!(apu.square1.length.counter, 1);
+        Ok(())
+    }
+}
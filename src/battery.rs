@@ -0,0 +1,66 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::cardridge::Cardridge;
+
+/// the save file is stored next to the rom, game.gb is saved to game.sav
+pub fn get_save_path(rom_path: &Path) -> PathBuf {
+    rom_path.with_extension("sav")
+}
+
+/// load the battery ram from the save file, a missing save file leaves the ram empty
+pub fn load(cardridge: &mut Cardridge, path: &Path) -> io::Result<()> {
+    match fs::read(path) {
+        Ok(data) => {
+            cardridge.load_battery_data(&data);
+            Ok(())
+        },
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(error) => Err(error),
+    }
+}
+
+/// the data is written to a temporary file first so the old save survives a crash while saving
+pub fn save(cardridge: &Cardridge, path: &Path) -> io::Result<()> {
+    let temporary = path.with_extension("sav.tmp");
+    fs::write(&temporary, cardridge.get_battery_data())?;
+    fs::rename(&temporary, path)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn test_save_path() -> Result<(), String> {
+        assert_eq!(get_save_path(Path::new("roms/game.gb")), PathBuf::from("roms/game.sav"));
+        assert_eq!(get_save_path(Path::new("game")), PathBuf::from("game.sav"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_load() -> Result<(), String> {
+        let path = env::temp_dir().join(format!("rustboy-test-{}.sav", process::id()));
+        let mut cardridge = Cardridge::rom_only(Vec::new());
+        cardridge.ram = vec![0; 0x2000];
+        load(&mut cardridge, &path).map_err(|e| e.to_string())?;
+        assert_eq!(cardridge.ram[0], 0);
+
+        cardridge.ram[0] = 0x12;
+        cardridge.ram[0x1fff] = 0x34;
+        save(&cardridge, &path).map_err(|e| e.to_string())?;
+        assert_eq!(fs::read(&path).map_err(|e| e.to_string())?.len(), 0x2000);
+
+        let mut loaded = Cardridge::rom_only(Vec::new());
+        loaded.ram = vec![0; 0x2000];
+        load(&mut loaded, &path).map_err(|e| e.to_string())?;
+        fs::remove_file(&path).map_err(|e| e.to_string())?;
+        assert_eq!(loaded.ram[0], 0x12);
+        assert_eq!(loaded.ram[0x1fff], 0x34);
+        Ok(())
+    }
+}
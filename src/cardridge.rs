@@ -1,3 +1,458 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::mbc::Mbc;
+use crate::rtc::Clock;
+
+const HEADER_END: usize = 0x150;
+
+/// the rom isn't part of a save state, it is taken from the running cardridge when a state is loaded
+#[derive(Serialize, Deserialize)]
 pub struct Cardridge {
+    #[serde(skip)]
     pub memory: Vec<u8>,
+    pub ram: Vec<u8>,
+    mbc: Mbc,
+    battery: bool,
+    /// set when the ram is written, cleared when the battery data is saved
+    ram_changed: bool,
+}
+
+/// the cardridge header at 0x0100-0x014f
+#[derive(Debug, PartialEq)]
+pub struct Header {
+    pub title: String,
+    pub manufacturer_code: String,
+    pub cgb_flag: u8,
+    pub licensee_code: String,
+    pub sgb_flag: u8,
+    pub cardridge_type: u8,
+    pub rom_size: usize,
+    pub ram_size: usize,
+    pub destination: Destination,
+    pub old_licensee_code: u8,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Destination {
+    Japan,
+    Overseas,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CardridgeError {
+    /// the rom is smaller than the header or the rom size in the header
+    Truncated { expected: usize, actual: usize },
+    UnknownRomSize(u8),
+    UnknownRamSize(u8),
+    UnsupportedCardridgeType(u8),
+    HeaderChecksum { expected: u8, actual: u8 },
+    GlobalChecksum { expected: u16, actual: u16 },
+}
+
+impl fmt::Display for CardridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CardridgeError::Truncated { expected, actual } =>
+                write!(f, "rom is truncated, expected {} bytes but got {}", expected, actual),
+            CardridgeError::UnknownRomSize(code) => write!(f, "unknown rom size {:#04x}", code),
+            CardridgeError::UnknownRamSize(code) => write!(f, "unknown ram size {:#04x}", code),
+            CardridgeError::UnsupportedCardridgeType(code) => write!(f, "unsupported cardridge type {:#04x}", code),
+            CardridgeError::HeaderChecksum { expected, actual } =>
+                write!(f, "header checksum is {:#04x} but the header contains {:#04x}", actual, expected),
+            CardridgeError::GlobalChecksum { expected, actual } =>
+                write!(f, "global checksum is {:#06x} but the header contains {:#06x}", actual, expected),
+        }
+    }
+}
+
+impl Cardridge {
+    /// create a cardridge from a rom dump, the header and its checksum are verified,
+    /// the global checksum isn't because the hardware never checks it, see validate
+    pub fn new(memory: Vec<u8>) -> Result<Self, CardridgeError> {
+        let header = Header::parse(&memory)?;
+        if memory.len() < header.rom_size {
+            return Err(CardridgeError::Truncated { expected: header.rom_size, actual: memory.len() });
+        }
+        let mbc = Mbc::new(header.cardridge_type, header.rom_size / 0x4000)
+            .ok_or(CardridgeError::UnsupportedCardridgeType(header.cardridge_type))?;
+
+        Ok(Cardridge {
+            memory,
+            ram: vec![0; mbc.get_ram_size(header.ram_size)],
+            mbc,
+            battery: header.has_battery(),
+            ram_changed: false,
+        })
+    }
+
+    /// a cardridge without a bank controller or ram, the header isn't checked
+    pub fn rom_only(memory: Vec<u8>) -> Self {
+        Cardridge {
+            memory,
+            ram: Vec::new(),
+            mbc: Mbc::RomOnly,
+            battery: false,
+            ram_changed: false,
+        }
+    }
+
+    pub fn get_header(&self) -> Result<Header, CardridgeError> {
+        Header::parse(&self.memory)
+    }
+
+    /// check the global checksum, patched roms and a lot of homebrew have a wrong one but still run
+    pub fn validate(&self) -> Result<(), CardridgeError> {
+        let header = self.get_header()?;
+        let global_checksum = Header::calculate_global_checksum(&self.memory);
+        if global_checksum != header.global_checksum {
+            return Err(CardridgeError::GlobalChecksum { expected: header.global_checksum, actual: global_checksum });
+        }
+        Ok(())
+    }
+
+    /// read a byte from 0x0000-0x7fff through the bank controller,
+    /// reading past the end of the rom returns 0xff like an open bus
+    pub fn read(&self, location: usize) -> u8 {
+        let address = self.mbc.get_rom_address(location);
+        *self.memory.get(address).unwrap_or(&0xff)
+    }
+
+    /// writes to 0x0000-0x7fff set the bank controller registers
+    pub fn write(&mut self, location: usize, value: u8) {
+        self.mbc.write(location, value);
+    }
+
+    pub fn read_ram(&self, location: usize) -> u8 {
+        self.mbc.read_ram(&self.ram, location)
+    }
+
+    pub fn write_ram(&mut self, location: usize, value: u8) {
+        if self.mbc.write_ram(&mut self.ram, location, value) {
+            self.ram_changed = true;
+        }
+    }
+
+    /// only cardridges with a battery keep their ram when the power is off
+    pub fn has_battery(&self) -> bool {
+        self.battery
+    }
+
+    /// returns true if the ram or the rtc was changed by a write since the last call
+    pub fn take_ram_changed(&mut self) -> bool {
+        std::mem::replace(&mut self.ram_changed, false)
+    }
+
+    /// returns the new rumble motor state if the game switched it since the last call
+    pub fn take_rumble_event(&mut self) -> Option<bool> {
+        self.mbc.take_rumble_event()
+    }
+
+    /// replace the clock of the rtc, does nothing for cardridges without one
+    pub fn set_rtc_clock(&mut self, clock: Box<dyn Clock>) {
+        if let Some(rtc) = self.mbc.get_rtc_mut() {
+            rtc.set_clock(clock);
+        }
+    }
+
+    /// the battery backed data, the external ram followed by the rtc registers if the cardridge has a clock
+    pub fn get_battery_data(&self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        if let Some(rtc) = self.mbc.get_rtc() {
+            data.extend(rtc.save());
+        }
+        data
+    }
+
+    pub fn load_battery_data(&mut self, data: &[u8]) {
+        let length = self.ram.len().min(data.len());
+        self.ram[..length].copy_from_slice(&data[..length]);
+        if let (Some(rtc), Some(rtc_data)) = (self.mbc.get_rtc_mut(), data.get(self.ram.len()..)) {
+            rtc.load(rtc_data);
+        }
+    }
+}
+
+impl Header {
+    pub fn parse(rom: &[u8]) -> Result<Self, CardridgeError> {
+        if rom.len() < HEADER_END {
+            return Err(CardridgeError::Truncated { expected: HEADER_END, actual: rom.len() });
+        }
+        let header_checksum = Self::calculate_header_checksum(rom);
+        if header_checksum != rom[0x14d] {
+            return Err(CardridgeError::HeaderChecksum { expected: rom[0x14d], actual: header_checksum });
+        }
+
+        // cgb cardridges use the end of the title for the manufacturer code and cgb flag
+        let cgb_flag = rom[0x143];
+        let (title, manufacturer_code) = if cgb_flag & 0x80 == 0x80 {
+            (Self::get_text(&rom[0x134..0x13f]), Self::get_text(&rom[0x13f..0x143]))
+        } else {
+            (Self::get_text(&rom[0x134..0x144]), String::new())
+        };
+
+        Ok(Header {
+            title,
+            manufacturer_code,
+            cgb_flag,
+            licensee_code: Self::get_text(&rom[0x144..0x146]),
+            sgb_flag: rom[0x146],
+            cardridge_type: rom[0x147],
+            rom_size: Self::get_rom_size(rom[0x148])?,
+            ram_size: Self::get_ram_size(rom[0x149])?,
+            destination: if rom[0x14a] == 0 { Destination::Japan } else { Destination::Overseas },
+            old_licensee_code: rom[0x14b],
+            version: rom[0x14c],
+            header_checksum: rom[0x14d],
+            global_checksum: u16::from_be_bytes([rom[0x14e], rom[0x14f]]),
+        })
+    }
+
+    pub fn has_battery(&self) -> bool {
+        matches!(self.cardridge_type, 0x03 | 0x06 | 0x09 | 0x0d | 0x0f | 0x10 | 0x13 | 0x1b | 0x1e | 0x22 | 0xff)
+    }
+
+    /// the checksum over 0x0134-0x014c the boot rom verifies
+    pub fn calculate_header_checksum(rom: &[u8]) -> u8 {
+        rom[0x134..=0x14c]
+            .iter()
+            .fold(0u8, |checksum, byte| checksum.wrapping_sub(*byte).wrapping_sub(1))
+    }
+
+    /// the sum of every byte in the rom except the global checksum itself
+    pub fn calculate_global_checksum(rom: &[u8]) -> u16 {
+        rom.iter()
+            .enumerate()
+            .filter(|(location, _)| *location != 0x14e && *location != 0x14f)
+            .fold(0u16, |checksum, (_, byte)| checksum.wrapping_add(u16::from(*byte)))
+    }
+
+    fn get_text(bytes: &[u8]) -> String {
+        bytes.iter()
+            .take_while(|byte| **byte != 0)
+            .map(|byte| char::from(*byte))
+            .collect()
+    }
+
+    /// rom size in bytes, 32 KiB shifted by the code,
+    /// 0x52-0x54 are the 72, 80 and 96 bank sizes some header databases list
+    fn get_rom_size(code: u8) -> Result<usize, CardridgeError> {
+        match code {
+            0x00..=0x08 => Ok(0x8000 << code),
+            0x52 => Ok(72 * 0x4000),
+            0x53 => Ok(80 * 0x4000),
+            0x54 => Ok(96 * 0x4000),
+            _ => Err(CardridgeError::UnknownRomSize(code)),
+        }
+    }
+
+    /// external ram size in bytes
+    fn get_ram_size(code: u8) -> Result<usize, CardridgeError> {
+        match code {
+            0x00 => Ok(0),
+            0x01 => Ok(0x800),
+            0x02 => Ok(0x2000),
+            0x03 => Ok(0x8000),
+            0x04 => Ok(0x20000),
+            0x05 => Ok(0x10000),
+            _ => Err(CardridgeError::UnknownRamSize(code)),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtc::RTC_SAVE_SIZE;
+
+    /// a 32 KiB rom with a valid header and checksums
+    fn get_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x13a].copy_from_slice(b"RUSTBO");
+        rom[0x147] = 0x01;
+        rom[0x14a] = 0x01;
+        rom[0x14c] = 0x02;
+        fix_checksums(&mut rom);
+        rom
+    }
+
+    fn fix_checksums(rom: &mut [u8]) {
+        rom[0x14d] = Header::calculate_header_checksum(rom);
+        let [high, low] = Header::calculate_global_checksum(rom).to_be_bytes();
+        rom[0x14e] = high;
+        rom[0x14f] = low;
+    }
+
+    #[test]
+    fn test_parse_header() -> Result<(), String> {
+        let cardridge = Cardridge::new(get_rom()).map_err(|e| e.to_string())?;
+        let header = cardridge.get_header().map_err(|e| e.to_string())?;
+        assert_eq!(header.title, "RUSTBO");
+        assert_eq!(header.manufacturer_code, "");
+        assert_eq!(header.cardridge_type, 0x01);
+        assert_eq!(header.rom_size, 0x8000);
+        assert_eq!(header.ram_size, 0);
+        assert_eq!(header.destination, Destination::Overseas);
+        assert_eq!(header.version, 0x02);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_cgb_header() -> Result<(), String> {
+        let mut rom = get_rom();
+        rom[0x134..0x144].copy_from_slice(b"POKEMON YEAAPSE\x80");
+        rom[0x148] = 0x01;
+        rom[0x149] = 0x03;
+        rom.resize(0x10000, 0);
+        fix_checksums(&mut rom);
+        let header = Cardridge::new(rom).map_err(|e| e.to_string())?.get_header().map_err(|e| e.to_string())?;
+        assert_eq!(header.title, "POKEMON YEA");
+        assert_eq!(header.manufacturer_code, "APSE");
+        assert_eq!(header.cgb_flag, 0x80);
+        assert_eq!(header.rom_size, 0x10000);
+        assert_eq!(header.ram_size, 0x8000);
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncated() -> Result<(), String> {
+        let error = Cardridge::new(vec![0x40, 0x41, 0x42]).err();
+        assert_eq!(error, Some(CardridgeError::Truncated { expected: HEADER_END, actual: 3 }));
+        let mut rom = get_rom();
+        rom.truncate(0x4000);
+        let error = Cardridge::new(rom).err();
+        assert_eq!(error, Some(CardridgeError::Truncated { expected: 0x8000, actual: 0x4000 }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_header_checksum() -> Result<(), String> {
+        let mut rom = get_rom();
+        rom[0x134] = b'X';
+        let error = Cardridge::new(rom).err();
+        assert!(matches!(error, Some(CardridgeError::HeaderChecksum { .. })));
+        Ok(())
+    }
+
+    #[test]
+    fn test_global_checksum() -> Result<(), String> {
+        let mut rom = get_rom();
+        rom[0x4000] = 0xff;
+        let cardridge = Cardridge::new(rom).map_err(|e| e.to_string())?;
+        assert!(matches!(cardridge.validate(), Err(CardridgeError::GlobalChecksum { .. })));
+        assert_eq!(Cardridge::new(get_rom()).map_err(|e| e.to_string())?.validate(), Ok(()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_mbc1_banking() -> Result<(), String> {
+        let mut rom = get_rom();
+        rom[0x147] = 0x03;
+        rom[0x148] = 0x02;
+        rom[0x149] = 0x03;
+        rom.resize(0x20000, 0);
+        rom[0x4000 * 5] = 0x55;
+        fix_checksums(&mut rom);
+        let mut cardridge = Cardridge::new(rom).map_err(|e| e.to_string())?;
+        assert_eq!(cardridge.ram.len(), 0x8000);
+        cardridge.write(0x2000, 0x05);
+        assert_eq!(cardridge.read(0x4000), 0x55);
+        cardridge.write(0x2000, 0x0d);
+        assert_eq!(cardridge.read(0x4000), 0x55);
+        cardridge.write(0x0000, 0x0a);
+        cardridge.write_ram(0xbfff, 0x66);
+        assert_eq!(cardridge.read_ram(0xbfff), 0x66);
+        assert_eq!(cardridge.ram[0x1fff], 0x66);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mbc3_battery_data() -> Result<(), String> {
+        let mut rom = get_rom();
+        rom[0x147] = 0x10;
+        rom[0x149] = 0x02;
+        fix_checksums(&mut rom);
+        let mut cardridge = Cardridge::new(rom).map_err(|e| e.to_string())?;
+        cardridge.write(0x0000, 0x0a);
+        cardridge.write_ram(0xa000, 0x12);
+        cardridge.write(0x4000, 0x09);
+        cardridge.write_ram(0xa000, 0x05);
+        let data = cardridge.get_battery_data();
+        assert_eq!(data.len(), 0x2000 + RTC_SAVE_SIZE);
+        assert_eq!(data[0], 0x12);
+        assert_eq!(data[0x2000 + 4], 0x05);
+
+        let mut rom = get_rom();
+        rom[0x147] = 0x10;
+        rom[0x149] = 0x02;
+        fix_checksums(&mut rom);
+        let mut loaded = Cardridge::new(rom).map_err(|e| e.to_string())?;
+        loaded.load_battery_data(&data);
+        assert_eq!(loaded.ram[0], 0x12);
+        assert_eq!(loaded.get_battery_data()[0x2000 + 4], 0x05);
+        Ok(())
+    }
+
+    #[test]
+    fn test_battery() -> Result<(), String> {
+        let mut cardridge = Cardridge::new(get_rom()).map_err(|e| e.to_string())?;
+        assert!(!cardridge.has_battery());
+        assert!(!cardridge.take_ram_changed());
+
+        let mut rom = get_rom();
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+        fix_checksums(&mut rom);
+        cardridge = Cardridge::new(rom).map_err(|e| e.to_string())?;
+        assert!(cardridge.has_battery());
+        // writes while the ram is disabled don't change it
+        cardridge.write_ram(0xa000, 0x12);
+        assert!(!cardridge.take_ram_changed());
+        cardridge.write(0x0000, 0x0a);
+        cardridge.write_ram(0xa000, 0x12);
+        assert!(cardridge.take_ram_changed());
+        assert!(!cardridge.take_ram_changed());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unsupported_type() -> Result<(), String> {
+        let mut rom = get_rom();
+        rom[0x147] = 0xfc;
+        fix_checksums(&mut rom);
+        assert_eq!(Cardridge::new(rom).err(), Some(CardridgeError::UnsupportedCardridgeType(0xfc)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_rom_size() -> Result<(), String> {
+        let mut rom = get_rom();
+        rom[0x148] = 0x09;
+        fix_checksums(&mut rom);
+        assert_eq!(Cardridge::new(rom).err(), Some(CardridgeError::UnknownRomSize(0x09)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_72_bank_rom() -> Result<(), String> {
+        let mut rom = get_rom();
+        rom[0x148] = 0x52;
+        rom.resize(72 * 0x4000, 0);
+        rom[71 * 0x4000] = 0x71;
+        fix_checksums(&mut rom);
+        let mut cardridge = Cardridge::new(rom).map_err(|e| e.to_string())?;
+        assert_eq!(cardridge.get_header().map_err(|e| e.to_string())?.rom_size, 0x120000);
+        cardridge.write(0x2000, 0x07);
+        cardridge.write(0x4000, 0x02);
+        assert_eq!(cardridge.read(0x4000), 0x71);
+        // bank 72-127 are past the end of the rom
+        cardridge.write(0x2000, 0x08);
+        assert_eq!(cardridge.read(0x4000), 0xff);
+        Ok(())
+    }
 }
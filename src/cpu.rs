@@ -1,9 +1,13 @@
 use sdl2::libc::OVERLAYFS_SUPER_MAGIC;
 use serde::de::value::{Error, self};
 
+use serde::{Deserialize, Serialize};
+
 use crate::cardridge::Cardridge;
 use crate::memory_map::MemoryMap;
+use crate::renderer::FRAME_CYCLES;
 
+#[derive(Serialize, Deserialize)]
 pub struct Cpu {
     b: u8,  // 000
     c: u8,  // 001
@@ -15,9 +19,13 @@ pub struct Cpu {
 
     f: u8,
     cycle_counter: usize,
-    memory_counter: usize,
+    memory_counter: u16,
     stack_counter: u16,
     stopped: bool,
+    halted: bool,
+    halt_bug: bool,
+    ime: bool,
+    ime_scheduled: bool,
     pub memory_map: MemoryMap
 }
 
@@ -25,20 +33,25 @@ const CPU_FIRST: u8  = 0b0000_0111;
 const CPU_SECOND: u8 = 0b0011_1000;
 impl Cpu {
     pub fn new(the_cardridge: Cardridge) -> Cpu {
+        // the registers as the boot rom leaves them, it ends by jumping to the entry point at 0x0100
         let mut cpu = Cpu {
-            b: 0,
-            c: 0,
-            d: 0,
-            e: 0,
-            h: 0,
-            l: 0,
-            a: 0,
-
-            f: 0,
-            memory_counter: 0,
+            b: 0x00,
+            c: 0x13,
+            d: 0x00,
+            e: 0xd8,
+            h: 0x01,
+            l: 0x4d,
+            a: 0x01,
+
+            f: 0xb0,
+            memory_counter: 0x0100,
             cycle_counter: 0,
-            stack_counter: 0,
+            stack_counter: 0xfffe,
             stopped: false,
+            halted: false,
+            halt_bug: false,
+            ime: false,
+            ime_scheduled: false,
             memory_map: MemoryMap::new(the_cardridge),
         };
         cpu.init();
@@ -46,28 +59,109 @@ impl Cpu {
     }
 
     pub fn start_cycle(&mut self) {
-        if !self.stopped {
-            loop {
-                if self.memory_counter >= self.memory_map.cardridge.memory.len().try_into().unwrap() {
-                    // create support for this
-                    return
-                }
-                if self.cycle_counter >=  69905 {
-                    self.cycle_counter = 0;
-                    return;
-                }
-                let number = self.get_from_cardridge();
-                self.run_opcode(number);
+        if self.stopped {
+            if !self.memory_map.joypad_line_low() {
+                return self.finish_stopped_frame();
+            }
+            self.stopped = false;
+        }
+        loop {
+            if self.cycle_counter >= FRAME_CYCLES {
+                self.cycle_counter = 0;
+                return;
+            }
+            if self.stopped {
+                return self.finish_stopped_frame();
+            }
+            self.step();
+        }
+    }
+
+    /// the system clock doesn't run while stopped, the audio is filled with silence
+    /// for the rest of the frame so every frame produces a frame of samples
+    fn finish_stopped_frame(&mut self) {
+        self.memory_map.apu.idle(FRAME_CYCLES.saturating_sub(self.cycle_counter));
+        self.cycle_counter = 0;
+    }
+
+    /// run one instruction and let the rest of the hardware catch up with the cycles it took
+    fn step(&mut self) {
+        let cycles_before = self.cycle_counter;
+        self.run_instruction();
+        self.memory_map.tick(self.cycle_counter - cycles_before);
+    }
+
+    /// handle pending interrupts, then fetch the opcode at the memory counter and run it
+    fn run_instruction(&mut self) {
+        if self.halted {
+            if self.memory_map.get_pending_interrupts() == 0 {
+                self.cycle_counter += 4;
+                return;
             }
+            self.halted = false;
+        }
+        if self.handle_interrupts() {
+            return;
+        }
+        // EI only takes effect after the instruction that follows it
+        let enable_ime = self.ime_scheduled;
+        let number = self.get_from_memory_counter();
+        if self.halt_bug {
+            // the memory counter isn't incremented past the opcode so the byte is read twice
+            self.halt_bug = false;
+            self.memory_counter = self.memory_counter.wrapping_sub(1);
+        }
+        self.run_opcode(number);
+        if enable_ime && self.ime_scheduled {
+            self.ime = true;
+            self.ime_scheduled = false;
+        }
+    }
+
+    /// jump to the handler of the highest priority pending interrupt,
+    /// returns true if an interrupt was dispatched
+    fn handle_interrupts(&mut self) -> bool {
+        let pending = self.memory_map.get_pending_interrupts();
+        if !self.ime || pending == 0 {
+            return false;
         }
+        let bit = pending.trailing_zeros() as u8;
+        self.memory_map.clear_interrupt(bit);
+        self.ime = false;
+        self.push_16bit(self.memory_counter);
+        self.memory_counter = 0x40 + u16::from(bit) * 8;
+        self.cycle_counter += 20;
+        true
     }
 
     fn init(&mut self) {
         self.memory_map.store_8bit_full_address(0xff00, 0x30);
+        // the sound registers as the boot rom leaves them
+        self.memory_map.store_8bit_full_address(0xff26, 0x80);
+        self.memory_map.store_8bit_full_address(0xff24, 0x77);
+        self.memory_map.store_8bit_full_address(0xff25, 0xf3);
+        self.memory_map.store_8bit_full_address(0xff11, 0x80);
+        self.memory_map.store_8bit_full_address(0xff12, 0xf3);
+    }
+
+    /// move the memory counter forward, it wraps around at the end of the address space
+    fn advance_memory_counter(&mut self, bytes: u16) {
+        self.memory_counter = self.memory_counter.wrapping_add(bytes);
     }
 
-    fn get_from_cardridge(&mut self) -> u8 {
-        return *self.memory_map.cardridge.memory.get(self.memory_counter).unwrap();
+    /// read the byte the memory counter points at through the memory map
+    fn get_from_memory_counter(&mut self) -> u8 {
+        self.memory_map.get_8bit_full_address(self.memory_counter.into())
+    }
+
+    /// read the little endian 16 bit operand after the opcode,
+    /// the memory counter is left on the high byte
+    fn get_16bit_from_memory_counter(&mut self) -> u16 {
+        self.advance_memory_counter(1);
+        let low: u16 = self.get_from_memory_counter().into();
+        self.advance_memory_counter(1);
+        let high: u16 = self.get_from_memory_counter().into();
+        (high << 8) + low
     }
 
     fn run_opcode(&mut self, opcode: u8) {
@@ -77,6 +171,7 @@ impl Cpu {
             0x02         => self.ldbca(),
             0x03         => self.incbc(),
             0x07         => self.rcla(),
+            0x08         => self.ld_a16_sp(),
             0x09         => self.add_hl_bc(),
             0x0a         => self.ldabc(),
             0x0b         => self.decbc(),
@@ -86,10 +181,12 @@ impl Cpu {
             0x12         => self.lddea(),
             0x13         => self.incde(),
             0x17         => self.rla(),
+            0x18         => self.jr(),
             0x19         => self.add_hl_de(),
             0x1a         => self.ldade(),
             0x1b         => self.decde(),
             0x1f         => self.rra(),
+            0x20 | 0x28 | 0x30 | 0x38 => self.jr_cc(opcode),
             0x21         => self.ld_hl(),
             0x22         => self.ldhlp(),
             0x23         => self.inchl(),
@@ -101,18 +198,36 @@ impl Cpu {
             0x31         => self.ld_sp(),
             0x32         => self.ldhlm(),
             0x33         => self.incsp(),
+            0x36         => self.ld_hl_d8(),
             0x39         => self.add_hl_sp(),
             0x37         => self.scf(),
             0x3a         => self.ldahlm(),
             0x3b         => self.decsp(),
             0x3f         => self.ccf(),
+            0xc0 | 0xc8 | 0xd0 | 0xd8 => self.ret_cc(opcode),
+            0xc1 | 0xd1 | 0xe1 | 0xf1 => self.pop(opcode),
+            0xc2 | 0xca | 0xd2 | 0xda => self.jp_cc(opcode),
+            0xc3         => self.jp(),
+            0xc4 | 0xcc | 0xd4 | 0xdc => self.call_cc(opcode),
+            0xc5 | 0xd5 | 0xe5 | 0xf5 => self.push(opcode),
+            0xc6 | 0xce | 0xd6 | 0xde | 0xe6 | 0xee | 0xf6 | 0xfe => self.alu_d8(opcode),
+            0xc9         => self.ret(),
             0xcb         => self.prefix_cb(),
+            0xcd         => self.call(),
+            0xd9         => self.reti(),
             0xe0         => self.ld_to_memory(),
             0xe2         => self.ld_to_memory_c(),
+            0xe8         => self.add_sp_e8(),
+            0xe9         => self.jp_hl(),
             0xea         => self.ld_a16_a(),
             0xf0         => self.ld_from_memory(),
             0xf2         => self.ld_from_memory_c(),
+            0xf3         => self.di(),
+            0xf8         => self.ld_hl_sp_e8(),
+            0xf9         => self.ld_sp_hl(),
             0xfa         => self.ld_a_a16(),
+            0xfb         => self.ei(),
+            0x76         => self.halt(),
             0x40..= 0x7f => self.ldrr(opcode),
             0x80..= 0x87 => self.add(opcode),
             0x88..= 0x8f => self.adc(opcode),
@@ -125,14 +240,15 @@ impl Cpu {
             t if t & 0xc7 == 0x06 => self.ld_from_cardridge(opcode),
             t if t & 0xc7 == 0x04 => self.inc(opcode),
             t if t & 0xc7 == 0x05 => self.dec(opcode),
+            t if t & 0xc7 == 0xc7 => self.rst(opcode),
             _ => self.default(opcode),
 
         }
     }
 
     fn prefix_cb(&mut self) {
-        self.memory_counter += 1;
-        let opcode = self.get_from_cardridge();
+        self.advance_memory_counter(1);
+        let opcode = self.get_from_memory_counter();
         match opcode {
             0x00..=0x07 => self.rlc(opcode),
             0x08..=0x0f => self.rrc(opcode),
@@ -140,14 +256,80 @@ impl Cpu {
             0x18..=0x1f => self.rr(opcode),
             0x20..=0x27 => self.sla(opcode),
             0x28..=0x2f => self.sra(opcode),
-            _=> self.default(opcode),
+            0x30..=0x37 => self.swap(opcode),
+            0x38..=0x3f => self.srl(opcode),
+            0x40..=0x7f => self.bit(opcode),
+            0x80..=0xbf => self.res(opcode),
+            0xc0..=0xff => self.set(opcode),
 
         }
         self.cycle_counter += 4;
     }
 
+    fn set(&mut self, opcode: u8) {
+        self.advance_memory_counter(1);
+        let register = opcode & CPU_FIRST;
+        let bit = (opcode & CPU_SECOND) >> 3;
+        let value_from_reg = self.get_value_from_register(register);
+
+        self.store_value_into_register(value_from_reg | (1 << bit), register);
+        self.cycle_counter += 4;
+    }
+
+    fn res(&mut self, opcode: u8) {
+        self.advance_memory_counter(1);
+        let register = opcode & CPU_FIRST;
+        let bit = (opcode & CPU_SECOND) >> 3;
+        let value_from_reg = self.get_value_from_register(register);
+
+        self.store_value_into_register(value_from_reg & !(1 << bit), register);
+        self.cycle_counter += 4;
+    }
+
+    fn bit(&mut self, opcode: u8) {
+        self.advance_memory_counter(1);
+        let register = opcode & CPU_FIRST;
+        let bit = (opcode & CPU_SECOND) >> 3;
+        let value_from_reg = self.get_value_from_register(register);
+
+        self.set_flag_z_value(value_from_reg & (1 << bit));
+        self.set_flag_n(false);
+        self.set_flag_h(true);
+        self.cycle_counter += 4;
+    }
+
+    fn srl(&mut self, opcode: u8) {
+        self.advance_memory_counter(1);
+        let register = opcode & CPU_FIRST;
+        let value_from_reg = self.get_value_from_register(register);
+
+        let result = value_from_reg >> 1;
+        self.set_flag_c(value_from_reg & 1 == 1);
+        self.set_flag_h(false);
+        self.set_flag_n(false);
+        self.set_flag_z_value(result);
+
+        self.store_value_into_register(result, register);
+        self.cycle_counter += 4;
+    }
+
+    fn swap(&mut self, opcode: u8) {
+        self.advance_memory_counter(1);
+        let register = opcode & CPU_FIRST;
+        let value_from_reg = self.get_value_from_register(register);
+
+        let result = value_from_reg.rotate_left(4);
+        self.set_flag_c(false);
+        self.set_flag_h(false);
+        self.set_flag_n(false);
+        self.set_flag_z_value(result);
+
+        self.store_value_into_register(result, register);
+        self.cycle_counter += 4;
+    }
+
     fn sra(&mut self, opcode: u8) {
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
         let register = u8::from(opcode & CPU_FIRST);
         let mut value_from_reg = self.get_value_from_register(register);
  
@@ -170,7 +352,7 @@ impl Cpu {
     }
 
     fn sla(&mut self, opcode: u8) {
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
         let register = u8::from(opcode & CPU_FIRST);
         let mut value_from_reg = self.get_value_from_register(register);
  
@@ -190,7 +372,7 @@ impl Cpu {
     }
 
     fn rr(&mut self, opcode: u8) {
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
         let register = u8::from(opcode & CPU_FIRST);
         let mut value_from_reg = self.get_value_from_register(register);
  
@@ -214,7 +396,7 @@ impl Cpu {
  
 
     fn rl(&mut self, opcode: u8) {
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
         let register = u8::from(opcode & CPU_FIRST);
         let mut value_from_reg = self.get_value_from_register(register);
  
@@ -236,7 +418,7 @@ impl Cpu {
     }
 
     fn rlc(&mut self, opcode: u8) {
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
         let register = u8::from(opcode & CPU_FIRST);
         let mut value_from_reg = self.get_value_from_register(register);
         let result = value_from_reg >> 7;
@@ -256,7 +438,7 @@ impl Cpu {
     }
 
     fn rrc(&mut self, opcode: u8) {
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
         let register = u8::from(opcode & CPU_FIRST);
         let mut value_from_reg = self.get_value_from_register(register);
 
@@ -277,7 +459,7 @@ impl Cpu {
     }
 
     fn rrca(&mut self) {
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
         let result = self.a & 1;
 
         self.a = self.a >> 1;
@@ -294,7 +476,7 @@ impl Cpu {
     }
 
     fn rra(&mut self) {
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
         let result = self.a & 1;
 
         self.a = self.a >> 1;
@@ -313,7 +495,7 @@ impl Cpu {
     }
 
     fn rla(&mut self) {
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
         let result = self.a >> 7;
 
         self.a = self.a << 1;
@@ -340,7 +522,7 @@ impl Cpu {
         self.set_flag_n(false);
         self.set_hl(overflow.0);
         self.cycle_counter += 8;
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
     }
 
     fn add_hl_bc(&mut self) {
@@ -363,7 +545,7 @@ impl Cpu {
     }
 
     fn rcla(&mut self) {
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
         let result = self.a >> 7;
         self.a = self.a << 1;
         if result == 1 {
@@ -381,7 +563,7 @@ impl Cpu {
         self.set_flag_c(true);
         self.set_flag_h(false);
         self.set_flag_n(false);
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
         self.cycle_counter += 4;
     }
 
@@ -389,7 +571,7 @@ impl Cpu {
         self.a = !self.a;
         self.set_flag_n(true);
         self.set_flag_h(true);
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
         self.cycle_counter += 4;
     }
 
@@ -397,7 +579,7 @@ impl Cpu {
         self.f  = self.f ^ 0x10;
         self.set_flag_n(false);
         self.set_flag_h(false);
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
         self.cycle_counter += 4;
     }
 
@@ -407,7 +589,7 @@ impl Cpu {
         if overflow.1 {
             self.b = self.b.overflowing_add(1).0;
         }
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
         self.cycle_counter += 8;
     }
 
@@ -417,7 +599,7 @@ impl Cpu {
         if overflow.1 {
             self.d = self.d.overflowing_add(1).0;
         }
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
         self.cycle_counter += 8;
     }
 
@@ -427,13 +609,13 @@ impl Cpu {
         if overflow.1 {
             self.h = self.h.overflowing_add(1).0;
         }
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
         self.cycle_counter += 8;
     }
 
     fn incsp(&mut self) {
-        let overflow = self.stack_counter.overflowing_add(1);
-        self.memory_counter += 1;
+        self.stack_counter = self.stack_counter.wrapping_add(1);
+        self.advance_memory_counter(1);
         self.cycle_counter += 8;
     }
 
@@ -443,7 +625,7 @@ impl Cpu {
         if overflow.1 {
             self.b = self.b.overflowing_sub(1).0;
         }
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
         self.cycle_counter += 8;
     }
 
@@ -453,7 +635,7 @@ impl Cpu {
         if overflow.1 {
             self.d = self.d.overflowing_sub(1).0;
         }
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
         self.cycle_counter += 8;
     }
 
@@ -463,73 +645,126 @@ impl Cpu {
         if overflow.1 {
             self.h = self.h.overflowing_sub(1).0;
         }
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
         self.cycle_counter += 8;
     }
 
     fn decsp(&mut self) {
-        let overflow = self.stack_counter.overflowing_sub(1);
-        self.memory_counter += 1;
+        self.stack_counter = self.stack_counter.wrapping_sub(1);
+        self.advance_memory_counter(1);
         self.cycle_counter += 8;
     }
 
     fn ld_bc(&mut self) {
-        self.memory_counter += 1;
-        self.b = self.get_from_cardridge();
-        self.memory_counter += 1;
-        self.c = self.get_from_cardridge();
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
+        self.c = self.get_from_memory_counter();
+        self.advance_memory_counter(1);
+        self.b = self.get_from_memory_counter();
+        self.advance_memory_counter(1);
         self.cycle_counter  += 12;
     }
 
     fn ld_de(&mut self) {
-        self.memory_counter += 1;
-        self.d = self.get_from_cardridge();
-        self.memory_counter += 1;
-        self.e = self.get_from_cardridge();
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
+        self.e = self.get_from_memory_counter();
+        self.advance_memory_counter(1);
+        self.d = self.get_from_memory_counter();
+        self.advance_memory_counter(1);
         self.cycle_counter  += 12;
     }
 
     fn ld_hl(&mut self) {
-        self.memory_counter += 1;
-        self.h = self.get_from_cardridge();
-        self.memory_counter += 1;
-        self.l = self.get_from_cardridge();
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
+        self.l = self.get_from_memory_counter();
+        self.advance_memory_counter(1);
+        self.h = self.get_from_memory_counter();
+        self.advance_memory_counter(1);
         self.cycle_counter  += 12;
     }
 
     fn ld_sp(&mut self) {
-        self.memory_counter += 1;
-        let mut high: u16 = self.get_from_cardridge().into();
-        self.memory_counter += 1;
-        let low: u16 = self.get_from_cardridge().into();
-        high = high << 8;
-        self.stack_counter = high + low;
-        self.memory_counter += 1;
+        self.stack_counter = self.get_16bit_from_memory_counter();
+        self.advance_memory_counter(1);
         self.cycle_counter  += 12;
     }
 
+    fn ld_a16_sp(&mut self) {
+        let location = self.get_16bit_from_memory_counter();
+        let [high, low] = self.stack_counter.to_be_bytes();
+        self.memory_map.store_8bit_full_address(location.into(), low);
+        self.memory_map.store_8bit_full_address(location.wrapping_add(1).into(), high);
+        self.advance_memory_counter(1);
+        self.cycle_counter += 20;
+    }
+
+    fn ld_sp_hl(&mut self) {
+        self.stack_counter = u16::from_be_bytes([self.h, self.l]);
+        self.advance_memory_counter(1);
+        self.cycle_counter += 8;
+    }
+
+    fn ld_hl_sp_e8(&mut self) {
+        let result = self.add_sp_offset();
+        self.set_hl(result);
+        self.advance_memory_counter(1);
+        self.cycle_counter += 12;
+    }
+
+    fn add_sp_e8(&mut self) {
+        self.stack_counter = self.add_sp_offset();
+        self.advance_memory_counter(1);
+        self.cycle_counter += 16;
+    }
+
+    /// add the signed operand to the stack counter, h and c are set
+    /// from the unsigned addition on the low byte
+    fn add_sp_offset(&mut self) -> u16 {
+        self.advance_memory_counter(1);
+        let value = self.get_from_memory_counter();
+        let low = self.stack_counter.to_be_bytes()[1];
+        self.set_flag_z(false);
+        self.set_flag_n(false);
+        self.set_flag_h_pos(low, value);
+        self.set_flag_c(low.overflowing_add(value).1);
+        self.stack_counter.wrapping_add_signed((value as i8).into())
+    }
+
+    fn push(&mut self, opcode: u8) {
+        let value = match opcode {
+            0xc5 => u16::from_be_bytes([self.b, self.c]),
+            0xd5 => u16::from_be_bytes([self.d, self.e]),
+            0xe5 => u16::from_be_bytes([self.h, self.l]),
+            _    => u16::from_be_bytes([self.a, self.f]),
+        };
+        self.push_16bit(value);
+        self.advance_memory_counter(1);
+        self.cycle_counter += 16;
+    }
+
+    fn pop(&mut self, opcode: u8) {
+        let [high, low] = self.pop_16bit().to_be_bytes();
+        match opcode {
+            0xc1 => (self.b, self.c) = (high, low),
+            0xd1 => (self.d, self.e) = (high, low),
+            0xe1 => (self.h, self.l) = (high, low),
+            // the lower 4 bits of f are always 0
+            _    => (self.a, self.f) = (high, low & 0xf0),
+        }
+        self.advance_memory_counter(1);
+        self.cycle_counter += 12;
+    }
+
     fn ld_a16_a(&mut self) {
-        self.memory_counter += 1;
-        let mut high: u16 = self.get_from_cardridge().into();
-        self.memory_counter += 1;
-        let low:u16 = self.get_from_cardridge().into();
-        high = high << 8;
-        self.memory_map.store_8bit_full_address((high + low).into(), self.a);
-        self.memory_counter += 1;
+        let location = self.get_16bit_from_memory_counter();
+        self.memory_map.store_8bit_full_address(location.into(), self.a);
+        self.advance_memory_counter(1);
         self.cycle_counter += 16;
     }
 
     fn ld_a_a16(&mut self) {
-        self.memory_counter += 1;
-        let mut high: u16 = self.get_from_cardridge().into();
-        self.memory_counter += 1;
-        let low:u16 = self.get_from_cardridge().into();
-        high = high << 8;
-        self.a = self.memory_map.get_8bit_full_address((high + low).into());
-        self.memory_counter += 1;
+        let location = self.get_16bit_from_memory_counter();
+        self.a = self.memory_map.get_8bit_full_address(location.into());
+        self.advance_memory_counter(1);
         self.cycle_counter += 16;
     }
 
@@ -544,7 +779,7 @@ impl Cpu {
         let location = self.get_hl();
         self.memory_map.store_8bit_full_address(location.into(), self.a);
         self.cycle_counter += 4;
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
     }
 
     fn ldhlp(&mut self) {
@@ -558,28 +793,28 @@ impl Cpu {
         let location = self.get_hl();
         self.memory_map.store_8bit_full_address(location.into(), self.a);
         self.cycle_counter += 4;
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
     }
 
     fn ldbca(&mut self) {
         let location = self.get_bc();
         self.memory_map.store_8bit_full_address(location.into(), self.a);
         self.cycle_counter += 4;
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
     }
 
     fn ldabc(&mut self) {
         let location = self.get_bc();
         self.a = self.memory_map.get_8bit_full_address(location.into());
         self.cycle_counter += 4;
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
     }
 
     fn ldade(&mut self) {
         let location = self.get_de();
         self.a = self.memory_map.get_8bit_full_address(location.into());
         self.cycle_counter += 4;
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
     }
 
     fn ldahlp(&mut self) {
@@ -591,7 +826,7 @@ impl Cpu {
         let location = self.get_hl();
         self.a = self.memory_map.get_8bit_full_address(location.into());
         self.cycle_counter += 4;
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
     }
 
     fn ldahlm(&mut self) {
@@ -603,7 +838,7 @@ impl Cpu {
         let location = self.get_hl();
         self.a = self.memory_map.get_8bit_full_address(location.into());
         self.cycle_counter += 4;
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
     }
 
 
@@ -611,44 +846,52 @@ impl Cpu {
         let location = self.get_de();
         self.memory_map.store_8bit_full_address(location.into(), self.a);
         self.cycle_counter += 4;
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
     }
 
     fn ld_to_memory(&mut self) {
-        self.memory_counter += 1;
-        let location = self.get_from_cardridge().clone();
+        self.advance_memory_counter(1);
+        let location = self.get_from_memory_counter().clone();
         self.memory_map.store_8bit(location, self.a);
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
         self.cycle_counter += 12;
     }
 
     fn ld_to_memory_c(&mut self) {
         self.memory_map.store_8bit(self.c, self.a);
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
         self.cycle_counter += 8;
     }
 
 
     fn ld_from_memory(&mut self) {
-        self.memory_counter += 1;
-        let location = self.get_from_cardridge().clone();
+        self.advance_memory_counter(1);
+        let location = self.get_from_memory_counter().clone();
         self.a = self.memory_map.get_8bit(location);
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
         self.cycle_counter += 12;
     }
 
     fn ld_from_memory_c(&mut self) {
         self.a = self.memory_map.get_8bit(self.c);
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
         self.cycle_counter += 8;
     }
 
     fn ld_from_cardridge(&mut self, opcode: u8) {
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
         let register = u8::from(opcode & CPU_SECOND);
-        let value = self.get_from_cardridge();
+        let value = self.get_from_memory_counter();
         self.store_value_into_register(value, register);
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
+        self.cycle_counter += 8;
+    }
+
+    fn ld_hl_d8(&mut self) {
+        self.advance_memory_counter(1);
+        let value = self.get_from_memory_counter();
+        self.store_hl_memory(value);
+        self.advance_memory_counter(1);
         self.cycle_counter += 8;
     }
 
@@ -657,113 +900,167 @@ impl Cpu {
         let second = u8::from(opcode & CPU_SECOND);
         let value = self.get_value_from_register(first);
         self.store_value_into_register(value, second);
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
         self.cycle_counter += 4;
     }
 
     fn sub(&mut self, opcode: u8) {
         let register = u8::from(opcode & CPU_FIRST);
         let value_from_reg: u8 = self.get_value_from_register(register);
-        let value_overflow = self.a.overflowing_sub(value_from_reg);
-        self.set_flag_z_value(value_overflow.0);
-        self.set_flag_n(true);
-        self.set_flag_h_neg(self.a, value_from_reg);
-        self.set_flag_c(value_overflow.1);
-        self.a = value_overflow.0;
-        self.memory_counter += 1;
+        self.sub_value(value_from_reg);
+        self.advance_memory_counter(1);
         self.cycle_counter += 4;
     }
 
     fn sbc(&mut self, opcode: u8) {
         let register = u8::from(opcode & CPU_FIRST);
         let value_from_reg = self.get_value_from_register(register);
-        let save_carry = value_from_reg.overflowing_add(self.get_c_value());
-        let value_overflow = self.a.overflowing_sub(save_carry.0);
-        self.set_flag_z_value(value_overflow.0);
-        self.set_flag_n(true);
-        self.set_flag_h_neg(self.a, value_from_reg);
-        self.set_flag_c(value_overflow.1);
-        self.a = value_overflow.0;
-        self.memory_counter += 1;
+        self.sbc_value(value_from_reg);
+        self.advance_memory_counter(1);
         self.cycle_counter += 4;
     }
 
     fn add(&mut self, opcode: u8) {
         let register = u8::from(opcode & CPU_FIRST);
         let value_from_reg = self.get_value_from_register(register);
-        let value_overflow = self.a.overflowing_add(value_from_reg);
-        self.set_flag_z_value(value_overflow.0);
-        self.set_flag_n(false);
-        self.set_flag_h_pos(self.a, value_from_reg);
-        self.set_flag_c(value_overflow.1);
-        self.a = value_overflow.0;
-        self.memory_counter += 1;
+        self.add_value(value_from_reg);
+        self.advance_memory_counter(1);
         self.cycle_counter += 4;
     }
 
     fn adc(&mut self, opcode: u8) {
         let register = u8::from(opcode & CPU_FIRST);
         let value_from_reg = self.get_value_from_register(register);
-        let save_carry = value_from_reg.overflowing_add(self.get_c_value());
-        let value_overflow = self.a.overflowing_add(save_carry.0);
-        self.set_flag_z_value(value_overflow.0);
-        self.set_flag_n(false);
-        self.set_flag_h_pos(self.a, value_from_reg);
-        self.set_flag_c(value_overflow.1);
-        self.a = value_overflow.0;
-        self.memory_counter += 1;
+        self.adc_value(value_from_reg);
+        self.advance_memory_counter(1);
         self.cycle_counter += 4;
     }
 
     fn and(&mut self, opcode: u8) {
         let register = u8::from(opcode & CPU_FIRST);
         let value_from_reg = self.get_value_from_register(register);
-        self.a = self.a & value_from_reg;
+        self.and_value(value_from_reg);
+        self.advance_memory_counter(1);
+        self.cycle_counter += 4;
+    }
+
+    fn xor(&mut self, opcode: u8) {
+        let register = u8::from(opcode & CPU_FIRST);
+        let value_from_reg = self.get_value_from_register(register);
+        self.xor_value(value_from_reg);
+        self.advance_memory_counter(1);
+        self.cycle_counter += 4;
+    }
+
+    fn or(&mut self, opcode: u8) {
+        let register = u8::from(opcode & CPU_FIRST);
+        let value_from_reg = self.get_value_from_register(register);
+        self.or_value(value_from_reg);
+        self.advance_memory_counter(1);
+        self.cycle_counter += 4;
+    }
+
+    fn cp(&mut self, opcode: u8) {
+        let register = u8::from(opcode & CPU_FIRST);
+        let value_from_reg = self.get_value_from_register(register);
+        self.cp_value(value_from_reg);
+        self.advance_memory_counter(1);
+        self.cycle_counter += 4;
+    }
+
+    /// the ALU instructions with an immediate operand, 0xc6 to 0xfe
+    fn alu_d8(&mut self, opcode: u8) {
+        self.advance_memory_counter(1);
+        let value = self.get_from_memory_counter();
+        match opcode {
+            0xc6 => self.add_value(value),
+            0xce => self.adc_value(value),
+            0xd6 => self.sub_value(value),
+            0xde => self.sbc_value(value),
+            0xe6 => self.and_value(value),
+            0xee => self.xor_value(value),
+            0xf6 => self.or_value(value),
+            _    => self.cp_value(value),
+        }
+        self.advance_memory_counter(1);
+        self.cycle_counter += 8;
+    }
+
+    fn sub_value(&mut self, value: u8) {
+        let value_overflow = self.a.overflowing_sub(value);
+        self.set_flag_z_value(value_overflow.0);
+        self.set_flag_n(true);
+        self.set_flag_h_neg(self.a, value);
+        self.set_flag_c(value_overflow.1);
+        self.a = value_overflow.0;
+    }
+
+    /// the carry is part of both the half and the full borrow
+    fn sbc_value(&mut self, value: u8) {
+        let carry = i16::from(self.get_c_value());
+        let result = i16::from(self.a) - i16::from(value) - carry;
+        let half = i16::from(self.a & 0xf) - i16::from(value & 0xf) - carry;
+        self.set_flag_z_value(result as u8);
+        self.set_flag_n(true);
+        self.set_flag_h(half < 0);
+        self.set_flag_c(result < 0);
+        self.a = result as u8;
+    }
+
+    fn add_value(&mut self, value: u8) {
+        let value_overflow = self.a.overflowing_add(value);
+        self.set_flag_z_value(value_overflow.0);
+        self.set_flag_n(false);
+        self.set_flag_h_pos(self.a, value);
+        self.set_flag_c(value_overflow.1);
+        self.a = value_overflow.0;
+    }
+
+    /// the carry is part of both the half and the full carry
+    fn adc_value(&mut self, value: u8) {
+        let carry = u16::from(self.get_c_value());
+        let result = u16::from(self.a) + u16::from(value) + carry;
+        let half = u16::from(self.a & 0xf) + u16::from(value & 0xf) + carry;
+        self.set_flag_z_value(result as u8);
+        self.set_flag_n(false);
+        self.set_flag_h(half > 0xf);
+        self.set_flag_c(result > 0xff);
+        self.a = result as u8;
+    }
+
+    fn and_value(&mut self, value: u8) {
+        self.a = self.a & value;
         self.f = 0x20;
         self.set_flag_z_value(self.a);
         self.set_flag_c(false);
         self.set_flag_h(true);
         self.set_flag_n(false);
-        self.memory_counter += 1;
-        self.cycle_counter += 4;
     }
 
-    fn xor(&mut self, opcode: u8) {
-        let register = u8::from(opcode & CPU_FIRST);
-        let value_from_reg = self.get_value_from_register(register);
-        self.a = self.a ^ value_from_reg;
+    fn xor_value(&mut self, value: u8) {
+        self.a = self.a ^ value;
         self.f = 0;
         self.set_flag_z_value(self.a);
         self.set_flag_c(false);
         self.set_flag_h(false);
         self.set_flag_n(false);
-        self.memory_counter += 1;
-        self.cycle_counter += 4;
     }
 
-    fn or(&mut self, opcode: u8) {
-        let register = u8::from(opcode & CPU_FIRST);
-        let value_from_reg = self.get_value_from_register(register);
-        self.a = self.a | value_from_reg;
+    fn or_value(&mut self, value: u8) {
+        self.a = self.a | value;
         self.f = 0x20;
         self.set_flag_z_value(self.a);
         self.set_flag_c(false);
         self.set_flag_h(false);
         self.set_flag_n(false);
-        self.memory_counter += 1;
-        self.cycle_counter += 4;
     }
 
-    fn cp(&mut self, opcode: u8) {
-        let register = u8::from(opcode & CPU_FIRST);
-        let value_from_reg = self.get_value_from_register(register);
-        let value_overflow = self.a.overflowing_sub(value_from_reg);
+    fn cp_value(&mut self, value: u8) {
+        let value_overflow = self.a.overflowing_sub(value);
         self.set_flag_z_value(value_overflow.0);
         self.set_flag_n(true);
-        self.set_flag_h_neg(self.a, value_from_reg);
+        self.set_flag_h_neg(self.a, value);
         self.set_flag_c(value_overflow.1);
-        self.memory_counter += 1;
-        self.cycle_counter += 4;
     }
 
     fn inc(&mut self, opcode: u8) {
@@ -774,7 +1071,7 @@ impl Cpu {
         self.set_flag_n(false);
         self.set_flag_h_pos(value, 1);
         self.store_value_into_register(overflow.0, register);
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
         self.cycle_counter += 4;
     }
 
@@ -786,7 +1083,7 @@ impl Cpu {
         self.set_flag_n(true);
         self.set_flag_h_neg(value, 1);
         self.store_value_into_register(overflow.0, register);
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
         self.cycle_counter += 4;
     }
 
@@ -819,10 +1116,134 @@ impl Cpu {
         self.set_flag_z_value(self.a);
         self.set_flag_c(overflow);
         self.set_flag_h(false);
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
+        self.cycle_counter += 4;
+    }
+
+    fn jp(&mut self) {
+        self.memory_counter = self.get_16bit_from_memory_counter();
+        self.cycle_counter += 16;
+    }
+
+    fn jp_cc(&mut self, opcode: u8) {
+        let location = self.get_16bit_from_memory_counter();
+        if self.get_condition(opcode) {
+            self.memory_counter = location;
+            self.cycle_counter += 16;
+        }
+        else {
+            self.advance_memory_counter(1);
+            self.cycle_counter += 12;
+        }
+    }
+
+    fn jp_hl(&mut self) {
+        self.memory_counter = (u16::from(self.h) << 8) + u16::from(self.l);
+        self.cycle_counter += 4;
+    }
+
+    fn jr(&mut self) {
+        self.advance_memory_counter(1);
+        let offset = self.get_from_memory_counter() as i8;
+        self.memory_counter = self.memory_counter.wrapping_add(1).wrapping_add_signed(offset.into());
+        self.cycle_counter += 12;
+    }
+
+    fn jr_cc(&mut self, opcode: u8) {
+        if self.get_condition(opcode) {
+            self.jr();
+        }
+        else {
+            self.advance_memory_counter(2);
+            self.cycle_counter += 8;
+        }
+    }
+
+    fn call(&mut self) {
+        let location = self.get_16bit_from_memory_counter();
+        self.push_16bit(self.memory_counter.wrapping_add(1));
+        self.memory_counter = location;
+        self.cycle_counter += 24;
+    }
+
+    fn call_cc(&mut self, opcode: u8) {
+        if self.get_condition(opcode) {
+            self.call();
+        }
+        else {
+            self.advance_memory_counter(3);
+            self.cycle_counter += 12;
+        }
+    }
+
+    fn ret(&mut self) {
+        self.memory_counter = self.pop_16bit();
+        self.cycle_counter += 16;
+    }
+
+    fn ret_cc(&mut self, opcode: u8) {
+        if self.get_condition(opcode) {
+            self.memory_counter = self.pop_16bit();
+            self.cycle_counter += 20;
+        }
+        else {
+            self.advance_memory_counter(1);
+            self.cycle_counter += 8;
+        }
+    }
+
+    fn reti(&mut self) {
+        self.ret();
+        self.ime = true;
+    }
+
+    fn di(&mut self) {
+        self.ime = false;
+        self.ime_scheduled = false;
+        self.advance_memory_counter(1);
         self.cycle_counter += 4;
     }
 
+    fn ei(&mut self) {
+        self.ime_scheduled = true;
+        self.advance_memory_counter(1);
+        self.cycle_counter += 4;
+    }
+
+    fn rst(&mut self, opcode: u8) {
+        self.push_16bit(self.memory_counter.wrapping_add(1));
+        self.memory_counter = u16::from(opcode & CPU_SECOND);
+        self.cycle_counter += 16;
+    }
+
+    /// push a 16 bit value on the stack, high byte first
+    fn push_16bit(&mut self, value: u16) {
+        let [high, low] = value.to_be_bytes();
+        self.stack_counter = self.stack_counter.wrapping_sub(1);
+        self.memory_map.store_8bit_full_address(self.stack_counter.into(), high);
+        self.stack_counter = self.stack_counter.wrapping_sub(1);
+        self.memory_map.store_8bit_full_address(self.stack_counter.into(), low);
+    }
+
+    /// pop a 16 bit value from the stack, low byte first
+    fn pop_16bit(&mut self) -> u16 {
+        let low = self.memory_map.get_8bit_full_address(self.stack_counter.into());
+        self.stack_counter = self.stack_counter.wrapping_add(1);
+        let high = self.memory_map.get_8bit_full_address(self.stack_counter.into());
+        self.stack_counter = self.stack_counter.wrapping_add(1);
+        u16::from_be_bytes([high, low])
+    }
+
+    /// condition in bit 3 and 4 of the opcode 00 NZ, 01 Z, 10 NC, 11 C
+    fn get_condition(&self, opcode: u8) -> bool {
+        match (opcode >> 3) & 0b11 {
+            0b00 => !self.get_flag_z(),
+            0b01 => self.get_flag_z(),
+            0b10 => !self.get_flag_c(),
+            _    => self.get_flag_c(),
+        }
+    }
+
     fn set_flag_z(&mut self, set: bool) {
         if set {
             self.f |= 0x80;
@@ -917,7 +1338,7 @@ impl Cpu {
     fn default(&mut self, byte: u8) {
         self.a = byte;
         println!("{:#04X?}", byte);
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
         self.cycle_counter += 1;
     }
 
@@ -949,15 +1370,14 @@ impl Cpu {
         }
     }
 
+    /// get_hl accounts for the 4 cycles of the memory access
     fn get_memory_hl(&mut self) -> u8 {
         let hl = self.get_hl();
-        self.cycle_counter += 4;
         self.memory_map.get_8bit_full_address(hl.into())
     }
 
     fn store_hl_memory(&mut self, value: u8) {
         let hl = self.get_hl();
-        self.cycle_counter += 4;
         self.memory_map.store_8bit_full_address(hl.into(), value);
     }
 
@@ -996,13 +1416,25 @@ impl Cpu {
 
     fn nop(&mut self) {
         self.cycle_counter += 4;
-        self.memory_counter += 1;
+        self.advance_memory_counter(1);
     }
 
     fn stop(&mut self) {
         self.cycle_counter += 4;
-        self.memory_counter += 2;
-        //self.stopped = true;
+        self.advance_memory_counter(2);
+        self.memory_map.store_8bit_full_address(0xff04, 0);
+        self.stopped = true;
+    }
+
+    fn halt(&mut self) {
+        self.cycle_counter += 4;
+        self.advance_memory_counter(1);
+        if !self.ime && self.memory_map.get_pending_interrupts() != 0 {
+            self.halt_bug = true;
+        }
+        else {
+            self.halted = true;
+        }
     }
 }
 
@@ -1010,12 +1442,11 @@ impl Cpu {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::memory_map::{Interrupt, INTERRUPT_MASK};
 
     fn get_cpu() -> Cpu {
         let vec1:Vec<u8> = vec![0x40, 0x41, 0x42];
-        let cardridge = Cardridge{
-            memory: vec1,
-        };
+        let cardridge = Cardridge::rom_only(vec1);
         Cpu {
             b: 1,
             c: 2,
@@ -1030,6 +1461,10 @@ mod tests {
             memory_counter: 0,
             stack_counter: 0,
             stopped: false,
+            halted: false,
+            halt_bug: false,
+            ime: false,
+            ime_scheduled: false,
             memory_map: MemoryMap::new(cardridge)
         }
     }
@@ -1179,8 +1614,8 @@ mod tests {
     fn test_ld_bc() -> Result<(), String> {
         let mut cpu = get_cpu();
         cpu.ld_bc();
-        assert_eq!(0x41, cpu.b);
-        assert_eq!(0x42, cpu.c);
+        assert_eq!(0x42, cpu.b);
+        assert_eq!(0x41, cpu.c);
         Ok(())
     }
 
@@ -1292,10 +1727,62 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_post_boot_state() -> Result<(), String> {
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0100] = 0x04;
+        let mut cpu = Cpu::new(Cardridge::rom_only(rom));
+        assert_eq!(u16::from_be_bytes([cpu.a, cpu.f]), 0x01b0);
+        assert_eq!(u16::from_be_bytes([cpu.b, cpu.c]), 0x0013);
+        assert_eq!(u16::from_be_bytes([cpu.d, cpu.e]), 0x00d8);
+        assert_eq!(u16::from_be_bytes([cpu.h, cpu.l]), 0x014d);
+        cpu.step();
+        assert_eq!(cpu.b, 1);
+        assert_eq!(cpu.memory_counter, 0x0101);
+        Ok(())
+    }
+
+    #[test]
+    fn test_post_boot_stack() -> Result<(), String> {
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0100..0x0103].copy_from_slice(&[0xcd, 0x00, 0x02]);   // call 0x0200
+        let mut cpu = Cpu::new(Cardridge::rom_only(rom));
+        cpu.memory_map.store_8bit_full_address(0xffff, 0x05);
+        cpu.step();
+        assert_eq!(cpu.stack_counter, 0xfffc);
+        assert_eq!(cpu.memory_map.get_8bit_full_address(0xfffc), 0x03);
+        assert_eq!(cpu.memory_map.get_8bit_full_address(0xfffd), 0x01);
+        assert_eq!(cpu.memory_map.get_8bit_full_address(0xffff), 0x05);
+        assert_eq!(cpu.memory_counter, 0x0200);
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory_counter_wraps() -> Result<(), String> {
+        let mut cpu = get_cpu();
+        cpu.memory_map.store_8bit_full_address(0xffff, 0x00);       // nop, the byte of IE
+        cpu.memory_counter = 0xffff;
+        cpu.step();
+        assert_eq!(cpu.memory_counter, 0x0000);
+        cpu.memory_map.store_8bit_full_address(0xfffd, 0xc4);       // call nz, with the flag z set
+        cpu.set_flag_z(true);
+        cpu.memory_counter = 0xfffd;
+        cpu.step();
+        assert_eq!(cpu.memory_counter, 0x0000);
+        cpu.memory_map.store_8bit_full_address(0xfffe, 0x06);       // ld b, with the operand at 0xffff
+        cpu.memory_counter = 0xfffe;
+        cpu.step();
+        assert_eq!(cpu.b, 0x00);
+        assert_eq!(cpu.memory_counter, 0x0000);
+        Ok(())
+    }
+
     #[test]
     fn test_memory_counter()-> Result<(), String> {
         let mut cpu = get_cpu();
-        cpu.start_cycle();
+        cpu.step();
+        cpu.step();
+        cpu.step();
         assert_eq!(cpu.memory_counter, 3);
         Ok(())
     }
@@ -1359,11 +1846,32 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_adc_carry_in() -> Result<(), String> {
+        let mut cpu = get_cpu();
+        cpu.a = 0x01;
+        cpu.b = 0xff;
+        cpu.f = 0x10;
+        cpu.run_opcode(0x88);      // 0x01 + 0xff + 1
+        assert_eq!(cpu.a, 0x01);
+        assert!(cpu.get_flag_c());
+        assert!(cpu.get_flag_h());
+        assert!(!cpu.get_flag_z());
+        cpu.a = 0x0f;
+        cpu.b = 0x00;
+        cpu.f = 0x10;
+        cpu.run_opcode(0x88);      // the carry makes the low nibble overflow
+        assert_eq!(cpu.a, 0x10);
+        assert!(cpu.get_flag_h());
+        assert!(!cpu.get_flag_c());
+        Ok(())
+    }
+
     #[test]
     fn test_ld_from_memory() -> Result<(), String> {
         let mut cpu = get_cpu();
-        cpu.run_opcode(0xf0);      // add b to a with carry
-        assert_eq!(cpu.a, 0);
+        cpu.run_opcode(0xf0);      // load STAT into a
+        assert_eq!(cpu.a, 0x86);   // mode 2 with LY equal to LYC
         Ok(())
     }
 
@@ -1382,9 +1890,7 @@ mod tests {
     #[test]
     fn test_ld_to_and_from_memory() -> Result<(), String> {
         let vec1:Vec<u8> = vec![0xe0, 0x80, 0xf0, 0x80];
-        let cardridge = Cardridge{
-            memory: vec1,
-        };
+        let cardridge = Cardridge::rom_only(vec1);
         let mut cpu = get_cpu();
         cpu.memory_map = MemoryMap::new(cardridge);
         cpu.run_opcode(0xe0);      // add b to a with carry
@@ -1415,6 +1921,7 @@ mod tests {
     #[test]
     fn test_get_store_hl()-> Result<(), String> {
         let mut cpu = get_cpu();
+        cpu.h = 0xc5;
         cpu.run_opcode(0x70);
         cpu.run_opcode(0x56);
         assert_eq!(cpu.d, cpu.b);
@@ -1424,16 +1931,19 @@ mod tests {
     #[test]
     fn test_multiple_ld_to_memory()-> Result<(), String> {
         let mut cpu = get_cpu();
+        cpu.b = 0xc1;
+        cpu.d = 0xc3;
+        cpu.h = 0xc5;
         cpu.run_opcode(0x02);
         cpu.run_opcode(0x12);
         cpu.run_opcode(0x22);
         cpu.run_opcode(0x32);
 
 
-        assert_eq!(cpu.memory_map.get_8bit_full_address(0x0102), cpu.a);
-        assert_eq!(cpu.memory_map.get_8bit_full_address(0x0304), cpu.a);
-        assert_eq!(cpu.memory_map.get_8bit_full_address(0x0507), cpu.a);
-        assert_eq!(cpu.memory_map.get_8bit_full_address(0x0506), cpu.a);
+        assert_eq!(cpu.memory_map.get_8bit_full_address(0xc102), cpu.a);
+        assert_eq!(cpu.memory_map.get_8bit_full_address(0xc304), cpu.a);
+        assert_eq!(cpu.memory_map.get_8bit_full_address(0xc507), cpu.a);
+        assert_eq!(cpu.memory_map.get_8bit_full_address(0xc506), cpu.a);
         assert_eq!(cpu.memory_map.get_8bit_full_address(0xffff), 0);
         Ok(())
     }
@@ -1441,10 +1951,13 @@ mod tests {
     #[test]
     fn test_multiple_from_memory()-> Result<(), String> {
         let mut cpu = get_cpu();
-        cpu.memory_map.store_8bit_full_address(0x0102, 10);
-        cpu.memory_map.store_8bit_full_address(0x0304, 20);
-        cpu.memory_map.store_8bit_full_address(0x0507, 30);
-        cpu.memory_map.store_8bit_full_address(0x0506, 40);
+        cpu.b = 0xc1;
+        cpu.d = 0xc3;
+        cpu.h = 0xc5;
+        cpu.memory_map.store_8bit_full_address(0xc102, 10);
+        cpu.memory_map.store_8bit_full_address(0xc304, 20);
+        cpu.memory_map.store_8bit_full_address(0xc507, 30);
+        cpu.memory_map.store_8bit_full_address(0xc506, 40);
 
 
         cpu.run_opcode(0x0a);
@@ -1461,18 +1974,16 @@ mod tests {
         assert_eq!(cpu.a, 0);
         cpu.l = 0xff;
         cpu.run_opcode(0x2a);
-        assert_eq!(cpu.get_hl(), 0x0600);
+        assert_eq!(cpu.get_hl(), 0xc600);
         cpu.run_opcode(0x3a);
-        assert_eq!(cpu.get_hl(), 0x05ff);
+        assert_eq!(cpu.get_hl(), 0xc5ff);
         Ok(())
     }
 
     #[test]
     fn test_ld_to_and_from_a16()-> Result<(), String> {
-        let vec1:Vec<u8> = vec![0xea, 0xff, 0x80, 0xfe, 0xff, 0x80];
-        let cardridge = Cardridge{
-            memory: vec1,
-        };
+        let vec1:Vec<u8> = vec![0xea, 0x80, 0xff, 0xfa, 0x80, 0xff];
+        let cardridge = Cardridge::rom_only(vec1);
 
         let mut cpu = get_cpu();
         cpu.memory_map.cardridge = cardridge;
@@ -1484,6 +1995,533 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_run_from_ram()-> Result<(), String> {
+        let mut cpu = get_cpu();
+        cpu.memory_map.store_8bit_full_address(0xff80, 0x3e);      // ld a, 0x42
+        cpu.memory_map.store_8bit_full_address(0xff81, 0x42);
+        cpu.memory_counter = 0xff80;
+        cpu.step();
+        assert_eq!(cpu.a, 0x42);
+        assert_eq!(cpu.memory_counter, 0xff82);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rom_is_read_only()-> Result<(), String> {
+        let mut cpu = get_cpu();
+        cpu.memory_map.store_8bit_full_address(0x0001, 0x99);
+        assert_eq!(cpu.memory_map.get_8bit_full_address(0x0001), 0x41);
+        assert_eq!(cpu.memory_map.get_8bit_full_address(0x7fff), 0xff);
+        Ok(())
+    }
+
+    fn get_cpu_with_rom(rom: Vec<u8>) -> Cpu {
+        let mut cpu = get_cpu();
+        cpu.memory_map.cardridge = Cardridge::rom_only(rom);
+        cpu.stack_counter = 0xfffe;
+        cpu
+    }
+
+    #[test]
+    fn test_jp() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0xc3, 0x50, 0x01]);
+        cpu.step();
+        assert_eq!(cpu.memory_counter, 0x0150);
+        assert_eq!(cpu.cycle_counter, 16);
+        Ok(())
+    }
+
+    #[test]
+    fn test_jp_cc() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0xca, 0x50, 0x01, 0xc2, 0x50, 0x01]);
+        cpu.step();
+        assert_eq!(cpu.memory_counter, 3);
+        assert_eq!(cpu.cycle_counter, 12);
+        cpu.step();
+        assert_eq!(cpu.memory_counter, 0x0150);
+        assert_eq!(cpu.cycle_counter, 28);
+        Ok(())
+    }
+
+    #[test]
+    fn test_jp_hl() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0xe9]);
+        cpu.step();
+        assert_eq!(cpu.memory_counter, 0x0506);
+        assert_eq!(cpu.cycle_counter, 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_jr() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0x00, 0x18, 0x02, 0x00, 0x00, 0x18, 0xfa]);
+        cpu.memory_counter = 1;
+        cpu.step();
+        assert_eq!(cpu.memory_counter, 5);
+        cpu.step();
+        assert_eq!(cpu.memory_counter, 1);
+        assert_eq!(cpu.cycle_counter, 24);
+        Ok(())
+    }
+
+    #[test]
+    fn test_jr_cc() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0x38, 0x10, 0x30, 0x10]);
+        cpu.step();
+        assert_eq!(cpu.memory_counter, 2);
+        assert_eq!(cpu.cycle_counter, 8);
+        cpu.step();
+        assert_eq!(cpu.memory_counter, 0x14);
+        assert_eq!(cpu.cycle_counter, 20);
+        Ok(())
+    }
+
+    #[test]
+    fn test_call_ret() -> Result<(), String> {
+        let mut rom = vec![0x00; 0x20];
+        rom[0] = 0xcd;
+        rom[1] = 0x10;
+        rom[2] = 0x00;
+        rom[0x10] = 0xc9;
+        let mut cpu = get_cpu_with_rom(rom);
+        cpu.step();
+        assert_eq!(cpu.memory_counter, 0x10);
+        assert_eq!(cpu.stack_counter, 0xfffc);
+        assert_eq!(cpu.memory_map.get_8bit_full_address(0xfffd), 0x00);
+        assert_eq!(cpu.memory_map.get_8bit_full_address(0xfffc), 0x03);
+        assert_eq!(cpu.cycle_counter, 24);
+        cpu.step();
+        assert_eq!(cpu.memory_counter, 3);
+        assert_eq!(cpu.stack_counter, 0xfffe);
+        assert_eq!(cpu.cycle_counter, 40);
+        Ok(())
+    }
+
+    #[test]
+    fn test_call_ret_cc() -> Result<(), String> {
+        let mut rom = vec![0x00; 0x20];
+        rom[0] = 0xdc;
+        rom[1] = 0x10;
+        rom[3] = 0xd4;
+        rom[4] = 0x10;
+        rom[0x10] = 0xd8;
+        rom[0x11] = 0xd0;
+        let mut cpu = get_cpu_with_rom(rom);
+        cpu.step();
+        assert_eq!(cpu.memory_counter, 3);
+        assert_eq!(cpu.cycle_counter, 12);
+        cpu.step();
+        assert_eq!(cpu.memory_counter, 0x10);
+        assert_eq!(cpu.cycle_counter, 36);
+        cpu.step();
+        assert_eq!(cpu.memory_counter, 0x11);
+        assert_eq!(cpu.cycle_counter, 44);
+        cpu.step();
+        assert_eq!(cpu.memory_counter, 6);
+        assert_eq!(cpu.cycle_counter, 64);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rst() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0x00, 0xef]);
+        cpu.memory_counter = 1;
+        cpu.step();
+        assert_eq!(cpu.memory_counter, 0x28);
+        assert_eq!(cpu.pop_16bit(), 2);
+        assert_eq!(cpu.cycle_counter, 16);
+        Ok(())
+    }
+
+    #[test]
+    fn test_push_pop() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0xc5, 0xd5, 0xe5, 0xf1, 0xe1, 0xd1, 0xc1]);
+        cpu.f = 0xb0;
+        for _ in 0..3 {
+            cpu.step();
+        }
+        assert_eq!(cpu.stack_counter, 0xfff8);
+        assert_eq!(cpu.memory_map.get_8bit_full_address(0xfffd), 1);
+        assert_eq!(cpu.memory_map.get_8bit_full_address(0xfffc), 2);
+        assert_eq!(cpu.cycle_counter, 48);
+        cpu.step();
+        assert_eq!(cpu.a, 5);
+        assert_eq!(cpu.f, 0x00);
+        for _ in 0..3 {
+            cpu.step();
+        }
+        assert_eq!((cpu.h, cpu.l), (3, 4));
+        assert_eq!((cpu.d, cpu.e), (1, 2));
+        assert_eq!((cpu.b, cpu.c), (0, 0));
+        assert_eq!(cpu.stack_counter, 0x0000);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pop_af_masks_flags() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0xf1]);
+        cpu.push_16bit(0x12ff);
+        cpu.step();
+        assert_eq!(cpu.a, 0x12);
+        assert_eq!(cpu.f, 0xf0);
+        assert_eq!(cpu.cycle_counter, 12);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ld_a16_sp() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0x08, 0x00, 0xc0]);
+        cpu.stack_counter = 0x1234;
+        cpu.step();
+        assert_eq!(cpu.memory_map.get_8bit_full_address(0xc000), 0x34);
+        assert_eq!(cpu.memory_map.get_8bit_full_address(0xc001), 0x12);
+        assert_eq!(cpu.memory_counter, 3);
+        assert_eq!(cpu.cycle_counter, 20);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ld_sp_hl() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0xf9, 0x33, 0x3b, 0x3b]);
+        cpu.step();
+        assert_eq!(cpu.stack_counter, 0x0506);
+        cpu.step();
+        assert_eq!(cpu.stack_counter, 0x0507);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.stack_counter, 0x0505);
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_sp_e8() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0xe8, 0x01, 0xe8, 0xfe]);
+        cpu.stack_counter = 0x00ff;
+        cpu.f = 0x80;
+        cpu.step();
+        assert_eq!(cpu.stack_counter, 0x0100);
+        assert_eq!(cpu.f, 0x30);
+        assert_eq!(cpu.cycle_counter, 16);
+        cpu.step();
+        assert_eq!(cpu.stack_counter, 0x00fe);
+        assert_eq!(cpu.f, 0x00);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ld_hl_sp_e8() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0xf8, 0xff]);
+        cpu.stack_counter = 0x0001;
+        cpu.step();
+        assert_eq!(cpu.get_hl(), 0x0000);
+        assert_eq!(cpu.stack_counter, 0x0001);
+        assert_eq!(cpu.f, 0x30);
+        assert_eq!(cpu.memory_counter, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_swap() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0xcb, 0x30, 0xcb, 0x31]);
+        cpu.b = 0x12;
+        cpu.c = 0;
+        cpu.f = 0x70;
+        cpu.step();
+        assert_eq!(cpu.b, 0x21);
+        assert_eq!(cpu.f, 0x00);
+        cpu.step();
+        assert!(cpu.get_flag_z());
+        assert_eq!(cpu.memory_counter, 4);
+        assert_eq!(cpu.cycle_counter, 16);
+        Ok(())
+    }
+
+    #[test]
+    fn test_srl() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0xcb, 0x3f, 0xcb, 0x3f]);
+        cpu.a = 0x81;
+        cpu.step();
+        assert_eq!(cpu.a, 0x40);
+        assert!(cpu.get_flag_c());
+        cpu.step();
+        assert_eq!(cpu.a, 0x20);
+        assert!(!cpu.get_flag_c());
+        Ok(())
+    }
+
+    #[test]
+    fn test_bit() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0xcb, 0x7f, 0xcb, 0x47]);
+        cpu.a = 0x01;
+        cpu.f = 0x10;
+        cpu.step();
+        assert!(cpu.get_flag_z());
+        assert!(cpu.get_flag_h());
+        assert!(!cpu.get_flag_n());
+        assert!(cpu.get_flag_c());
+        cpu.step();
+        assert!(!cpu.get_flag_z());
+        assert_eq!(cpu.a, 0x01);
+        assert_eq!(cpu.cycle_counter, 16);
+        Ok(())
+    }
+
+    #[test]
+    fn test_res_set() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0xcb, 0xf8, 0xcb, 0x80]);
+        cpu.b = 0x01;
+        cpu.step();
+        assert_eq!(cpu.b, 0x81);
+        cpu.step();
+        assert_eq!(cpu.b, 0x80);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cb_hl_timing() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0xcb, 0x46, 0xcb, 0xc6, 0xcb, 0x86]);
+        cpu.h = 0xc0;
+        cpu.l = 0x00;
+        cpu.step();
+        assert_eq!(cpu.cycle_counter, 12);
+        assert!(cpu.get_flag_z());
+        cpu.step();
+        assert_eq!(cpu.cycle_counter, 28);
+        assert_eq!(cpu.memory_map.get_8bit_full_address(0xc000), 0x01);
+        cpu.step();
+        assert_eq!(cpu.cycle_counter, 44);
+        assert_eq!(cpu.memory_map.get_8bit_full_address(0xc000), 0x00);
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_adc_d8() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0xc6, 0xf9, 0xce, 0x00]);
+        cpu.step();
+        assert_eq!(cpu.a, 0x00);
+        assert!(cpu.get_flag_z());
+        assert!(cpu.get_flag_c());
+        assert!(cpu.get_flag_h());
+        assert_eq!(cpu.cycle_counter, 8);
+        cpu.step();
+        assert_eq!(cpu.a, 0x01);
+        assert!(!cpu.get_flag_c());
+        assert_eq!(cpu.memory_counter, 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sub_sbc_d8() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0xd6, 0x08, 0xde, 0x00]);
+        cpu.step();
+        assert_eq!(cpu.a, 0xff);
+        assert!(cpu.get_flag_c());
+        assert!(cpu.get_flag_n());
+        cpu.step();
+        assert_eq!(cpu.a, 0xfe);
+        assert!(!cpu.get_flag_c());
+        Ok(())
+    }
+
+    #[test]
+    fn test_and_xor_or_d8() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0xe6, 0x03, 0xee, 0x03, 0xf6, 0x80]);
+        cpu.step();
+        assert_eq!(cpu.a, 0x03);
+        assert!(cpu.get_flag_h());
+        cpu.step();
+        assert_eq!(cpu.a, 0x00);
+        assert!(cpu.get_flag_z());
+        cpu.step();
+        assert_eq!(cpu.a, 0x80);
+        assert!(!cpu.get_flag_z());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cp_d8() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0xfe, 0x90, 0xfe, 0x07]);
+        cpu.step();
+        assert_eq!(cpu.a, 0x07);
+        assert!(cpu.get_flag_c());
+        assert!(!cpu.get_flag_z());
+        cpu.step();
+        assert!(cpu.get_flag_z());
+        assert!(!cpu.get_flag_c());
+        Ok(())
+    }
+
+    #[test]
+    fn test_ld_hl_d8() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0x36, 0x99]);
+        cpu.h = 0xc0;
+        cpu.l = 0x10;
+        cpu.step();
+        assert_eq!(cpu.memory_map.get_8bit_full_address(0xc010), 0x99);
+        assert_eq!(cpu.h, 0xc0);
+        assert_eq!(cpu.l, 0x10);
+        assert_eq!(cpu.memory_counter, 2);
+        assert_eq!(cpu.cycle_counter, 12);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ei_delay() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0xfb, 0x00, 0x00]);
+        cpu.memory_map.store_8bit_full_address(0xffff, 0x01);
+        cpu.memory_map.request_interrupt(Interrupt::VBlank);
+        cpu.step();
+        assert!(!cpu.ime);
+        cpu.step();
+        assert!(cpu.ime);
+        assert_eq!(cpu.memory_counter, 2);
+        cpu.step();
+        assert_eq!(cpu.memory_counter, 0x40);
+        assert_eq!(cpu.pop_16bit(), 2);
+        assert!(!cpu.ime);
+        assert_eq!(cpu.memory_map.get_8bit_full_address(0xff0f) & INTERRUPT_MASK, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_di() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0xfb, 0xf3, 0x00]);
+        cpu.memory_map.store_8bit_full_address(0xffff, 0x01);
+        cpu.memory_map.request_interrupt(Interrupt::VBlank);
+        cpu.step();
+        cpu.step();
+        cpu.step();
+        assert!(!cpu.ime);
+        assert_eq!(cpu.memory_counter, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_interrupt_priority() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0x00]);
+        cpu.ime = true;
+        cpu.memory_map.store_8bit_full_address(0xffff, 0x1e);
+        cpu.memory_map.request_interrupt(Interrupt::Joypad);
+        cpu.memory_map.request_interrupt(Interrupt::Timer);
+        cpu.memory_map.request_interrupt(Interrupt::VBlank);
+        cpu.step();
+        assert_eq!(cpu.memory_counter, 0x50);
+        assert_eq!(cpu.cycle_counter, 20);
+        assert_eq!(cpu.memory_map.get_8bit_full_address(0xff0f), 0xe0 | 0x11);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reti() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0xd9]);
+        cpu.push_16bit(0x1234);
+        cpu.step();
+        assert!(cpu.ime);
+        assert_eq!(cpu.memory_counter, 0x1234);
+        Ok(())
+    }
+
+    #[test]
+    fn test_halt() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0x76, 0x00]);
+        cpu.ime = true;
+        cpu.memory_map.store_8bit_full_address(0xffff, 0x04);
+        cpu.step();
+        cpu.step();
+        cpu.step();
+        assert!(cpu.halted);
+        assert_eq!(cpu.memory_counter, 1);
+        assert_eq!(cpu.cycle_counter, 12);
+        cpu.memory_map.request_interrupt(Interrupt::Timer);
+        cpu.step();
+        assert!(!cpu.halted);
+        assert_eq!(cpu.memory_counter, 0x50);
+        assert_eq!(cpu.pop_16bit(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_halt_without_ime() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0x76, 0x04]);
+        cpu.memory_map.store_8bit_full_address(0xffff, 0x04);
+        cpu.step();
+        assert!(cpu.halted);
+        cpu.memory_map.request_interrupt(Interrupt::Timer);
+        cpu.step();
+        assert!(!cpu.halted);
+        assert_eq!(cpu.b, 2);
+        assert_eq!(cpu.memory_counter, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_halt_bug() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0x76, 0x3e, 0x14]);
+        cpu.memory_map.store_8bit_full_address(0xffff, 0x04);
+        cpu.memory_map.request_interrupt(Interrupt::Timer);
+        cpu.step();
+        assert!(!cpu.halted);
+        cpu.step();
+        assert_eq!(cpu.a, 0x3e);
+        assert_eq!(cpu.memory_counter, 2);
+        cpu.step();
+        assert_eq!(cpu.d, 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_stop() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0x10, 0x00, 0x00]);
+        cpu.start_cycle();
+        assert!(cpu.stopped);
+        assert_eq!(cpu.memory_counter, 2);
+        cpu.start_cycle();
+        assert_eq!(cpu.memory_counter, 2);
+        cpu.memory_map.store_8bit_full_address(0xff00, 0x10);
+        cpu.memory_map.store_buttons(0x0e);
+        cpu.stopped = true;
+        cpu.memory_map.store_buttons(0x0f);
+        cpu.start_cycle();
+        assert!(cpu.stopped);
+        cpu.memory_map.store_buttons(0x0e);
+        cpu.stopped = true;
+        cpu.start_cycle();
+        assert!(!cpu.stopped);
+        Ok(())
+    }
+
+    #[test]
+    fn test_stopped_frame_audio() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0x10, 0x00, 0x00]);
+        cpu.memory_map.apu.take_samples();
+        cpu.start_cycle();
+        assert!(cpu.stopped);
+        cpu.start_cycle();
+        assert!(cpu.stopped);
+        // two frames of 70224 T-cycles at 48000 Hz, left and right
+        assert_eq!(cpu.memory_map.apu.take_samples().len(), 2 * 1607);
+        Ok(())
+    }
+
+    #[test]
+    fn test_timer_interrupt() -> Result<(), String> {
+        let mut cpu = get_cpu_with_rom(vec![0x00; 0x100]);
+        cpu.ime = true;
+        cpu.memory_map.store_8bit_full_address(0xffff, 0x04);
+        cpu.memory_map.store_8bit_full_address(0xff06, 0x80);
+        cpu.memory_map.store_8bit_full_address(0xff05, 0xff);
+        cpu.memory_map.store_8bit_full_address(0xff07, 0x05);
+        for _ in 0..5 {
+            cpu.step();
+        }
+        assert_eq!(cpu.memory_map.get_8bit_full_address(0xff05), 0x80);
+        assert_eq!(cpu.memory_counter, 5);
+        cpu.step();
+        assert_eq!(cpu.memory_counter, 0x50);
+        Ok(())
+    }
+
     #[test]
     fn test_h_flag() -> Result<(), String> {
         let mut cpu = get_cpu();
@@ -1523,6 +2561,26 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_sbc_carry_in() -> Result<(), String> {
+        let mut cpu = get_cpu();
+        cpu.a = 0x01;
+        cpu.b = 0xff;
+        cpu.f = 0x10;
+        cpu.run_opcode(0x98);      // 0x01 - 0xff - 1
+        assert_eq!(cpu.a, 0x01);
+        assert!(cpu.get_flag_c());
+        assert!(cpu.get_flag_h());
+        cpu.a = 0x10;
+        cpu.b = 0x00;
+        cpu.f = 0x10;
+        cpu.run_opcode(0x98);      // the carry makes the low nibble borrow
+        assert_eq!(cpu.a, 0x0f);
+        assert!(cpu.get_flag_h());
+        assert!(!cpu.get_flag_c());
+        Ok(())
+    }
+
     #[test]
     fn test_and_xor_or() -> Result<(), String> {
         let mut cpu = get_cpu();
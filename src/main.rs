@@ -1,23 +1,50 @@
+mod apu;
+mod battery;
 mod cpu;
 mod cardridge;
+mod mbc;
 mod memory_map;
+mod options;
+mod pacing;
 mod renderer;
+mod rewind;
+mod rtc;
+mod save_state;
 mod settings;
+mod timer;
+mod wav;
 
 extern crate sdl2;
-use sdl2::EventPump;
+use sdl2::{EventPump, Sdl};
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
 use sdl2::pixels::Color;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use sdl2::rect::{Point, Rect};
 use sdl2::render::{TextureCreator, Canvas};
 use sdl2::video::{WindowContext, Window};
+use apu::Apu;
+use cardridge::Cardridge;
+use options::Options;
+use pacing::FramePacer;
+use rewind::Rewind;
+use rtc::FixedClock;
 use settings::Settings;
 
 use std::env;
 use std::fs::File;
 use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
 use std::time::Duration;
+
+/// the battery ram is saved every ten seconds if the game changed it
+const BATTERY_SAVE_FRAMES: usize = 600;
+/// samples are dropped instead of queued when this many seconds of audio are waiting
+const MAX_AUDIO_LATENCY: f32 = 0.25;
+/// the seconds of audio kept queued when the emulation is paced by the audio device
+const AUDIO_SYNC_LATENCY: f32 = 0.05;
 // handle the annoying Rect i32
 macro_rules! rect(
     ($x:expr, $y:expr, $w:expr, $h:expr) => (
@@ -31,7 +58,9 @@ pub struct Sdl2Helper {
     debug_message: Vec<String>,
     canvas: Canvas<Window>,
     event_pump: EventPump,
-    texture_creator: TextureCreator<WindowContext>
+    texture_creator: TextureCreator<WindowContext>,
+    /// None when there is no audio device, the emulator then runs without sound
+    audio: Option<AudioQueue<i16>>,
 }
 
 impl Sdl2Helper {
@@ -49,13 +78,15 @@ impl Sdl2Helper {
         let canvas = win.into_canvas().build().unwrap();
         let event_pump = sdl_con.event_pump().unwrap();
         let texture_creator = canvas.texture_creator();
+        let audio = Self::open_audio(&sdl_con, settings.sample_rate);
         let sdl = Sdl2Helper {
             settings,
             width,
             debug_message: Vec::new(),
             canvas,
             event_pump,
-            texture_creator
+            texture_creator,
+            audio,
         };
         return sdl;
     }
@@ -90,42 +121,283 @@ impl Sdl2Helper {
     fn add_debug_message(&mut self, value: String) {
         self.debug_message.push(value);
     }
+
+    fn open_audio(sdl_con: &Sdl, sample_rate: u32) -> Option<AudioQueue<i16>> {
+        let desired = AudioSpecDesired {
+            freq: i32::try_from(sample_rate).ok(),
+            channels: Some(2),
+            samples: Some(1024),
+        };
+        let queue = sdl_con.audio().and_then(|audio| audio.open_queue::<i16, _>(None, &desired));
+        match queue {
+            Ok(queue) => {
+                queue.resume();
+                Some(queue)
+            },
+            Err(error) => {
+                eprintln!("could not open the audio device: {}", error);
+                None
+            }
+        }
+    }
+
+    /// queue the samples of the last frame, they are dropped while too much audio is waiting
+    fn queue_audio(&self, samples: &[i16]) {
+        let Some(audio) = &self.audio else {
+            return;
+        };
+        let bytes_per_second = audio.spec().freq as f32 * 4.0;
+        if (audio.size() as f32) < bytes_per_second * MAX_AUDIO_LATENCY {
+            if let Err(error) = audio.queue_audio(samples) {
+                eprintln!("could not queue audio: {}", error);
+            }
+        }
+    }
+
+    /// wait until the audio device has played the queue down to the target and
+    /// adjust the sample rate to the fill level, returns false without an audio device
+    fn sync_to_audio(&self, apu: &mut Apu) -> bool {
+        let Some(audio) = &self.audio else {
+            return false;
+        };
+        let frequency = audio.spec().freq.unsigned_abs();
+        let target = (frequency as f32 * 4.0 * AUDIO_SYNC_LATENCY) as u32;
+        apu.set_sample_rate(pacing::get_dynamic_sample_rate(frequency, audio.size(), target));
+        while audio.size() > target {
+            thread::sleep(Duration::from_millis(1));
+        }
+        true
+    }
     
 
 }
-fn get_rom() -> Vec<u8> {
-    let args: Vec<String> = env::args().collect();
-    let binding = "default".to_string();
-    let arg = args.get(1).unwrap_or(&binding);
-    let mut buffer: Vec<u8> = Vec::new();
-    if arg.eq("default") {
+fn get_rom(rom_path: Option<&Path>) -> Vec<u8> {
+    let Some(path) = rom_path else {
         return vec![0x40, 0x41, 0x42];
-    }
-    let file = File::open(arg);
+    };
+    let mut buffer: Vec<u8> = Vec::new();
+    let file = File::open(path);
     match file {
         Ok(mut val) => {
-            let _ = val.read_to_end(&mut buffer);
-            return buffer;
+            if let Err(error) = val.read_to_end(&mut buffer) {
+                eprintln!("could not read {}: {}", path.display(), error);
+                process::exit(1);
+            }
+            buffer
+        },
+        Err(error) => {
+            eprintln!("could not open {}: {}", path.display(), error);
+            process::exit(1);
+        },
+    }
+}
+
+/// load the rom from the arguments, without a rom a small test program is run
+fn get_cardridge(rom_path: Option<&Path>) -> Cardridge {
+    let rom = get_rom(rom_path);
+    if rom_path.is_none() {
+        return Cardridge::rom_only(rom);
+    }
+    match Cardridge::new(rom) {
+        Ok(cardridge) => {
+            if let Ok(header) = cardridge.get_header() {
+                println!("loaded {} version {}", header.title, header.version);
+            }
+            if let Err(error) = cardridge.validate() {
+                eprintln!("warning: {}", error);
+            }
+            cardridge
         },
-        Err(_) => return vec![0x40, 0x41, 0x42],
+        Err(error) => {
+            eprintln!("invalid rom: {}", error);
+            process::exit(1);
+        }
+    }
+}
+
+/// the save file for cardridges with a battery
+fn get_save_path(cardridge: &Cardridge, rom_path: Option<&Path>) -> Option<PathBuf> {
+    let rom_path = rom_path?;
+    if !cardridge.has_battery() {
+        return None;
+    }
+    Some(battery::get_save_path(rom_path))
+}
+
+/// load the ram from the save file when it exists, a save file that can't be read stops the
+/// emulator so the game can't overwrite it
+fn load_battery(cardridge: &mut Cardridge, save_path: &Option<PathBuf>) {
+    if let Some(path) = save_path {
+        if let Err(error) = battery::load(cardridge, path) {
+            eprintln!("could not load {}: {}", path.display(), error);
+            process::exit(1);
+        }
+    }
+}
+
+fn save_battery(cardridge: &Cardridge, save_path: &Option<PathBuf>) {
+    if let Some(path) = save_path {
+        if let Err(error) = battery::save(cardridge, path) {
+            eprintln!("could not save {}: {}", path.display(), error);
+        }
+    }
+}
+
+/// the number keys select the save state slot
+fn get_slot(keycode: Keycode) -> Option<u8> {
+    match keycode {
+        Keycode::Num1 => Some(1),
+        Keycode::Num2 => Some(2),
+        Keycode::Num3 => Some(3),
+        Keycode::Num4 => Some(4),
+        Keycode::Num5 => Some(5),
+        Keycode::Num6 => Some(6),
+        Keycode::Num7 => Some(7),
+        Keycode::Num8 => Some(8),
+        Keycode::Num9 => Some(9),
+        _ => None,
+    }
+}
+
+fn save_state(cpu: &cpu::Cpu, rom_path: Option<&Path>, slot: u8) {
+    let Some(rom_path) = rom_path else {
+        eprintln!("save states need a rom");
+        return;
+    };
+    let path = save_state::get_slot_path(rom_path, slot);
+    match save_state::save_to_file(cpu, &path) {
+        Ok(()) => println!("saved state to slot {}", slot),
+        Err(error) => eprintln!("could not save {}: {}", path.display(), error),
+    }
+}
+
+fn load_state(cpu: &mut cpu::Cpu, rom_path: Option<&Path>, slot: u8) {
+    let Some(rom_path) = rom_path else {
+        eprintln!("save states need a rom");
+        return;
+    };
+    let path = save_state::get_slot_path(rom_path, slot);
+    match save_state::load_from_file(cpu, &path) {
+        Ok(()) => println!("loaded state from slot {}", slot),
+        Err(error) => eprintln!("could not load {}: {}", path.display(), error),
+    }
+}
+
+/// the hotkey records to the --wav file, or next to the rom when there is none
+fn get_recording_path(options: &Options) -> PathBuf {
+    if let Some(path) = &options.wav {
+        return path.clone();
+    }
+    match &options.rom {
+        Some(rom_path) => rom_path.with_extension("wav"),
+        None => PathBuf::from("rustboy.wav"),
+    }
+}
+
+fn start_recording(apu: &mut Apu, options: &Options, path: &Path) {
+    apu.start_recording(options.wav_rate, options.wav_channels);
+    println!("recording audio to {}", path.display());
+}
+
+/// write the recording, the channels are written next to it as file.ch1.wav to file.ch4.wav
+fn stop_recording(apu: &mut Apu, path: &Path) {
+    let Some(recording) = apu.stop_recording() else {
+        return;
+    };
+    let mut files = vec![(path.to_path_buf(), 2, recording.mixed)];
+    if let Some(channels) = recording.channels {
+        for (index, samples) in channels.into_iter().enumerate() {
+            files.push((wav::get_channel_path(path, index + 1), 1, samples));
+        }
+    }
+    for (file, channels, samples) in files {
+        match wav::write(&file, recording.sample_rate, channels, &samples) {
+            Ok(()) => println!("saved audio to {}", file.display()),
+            Err(error) => eprintln!("could not save {}: {}", file.display(), error),
+        }
+    }
+}
+
+/// print when the rumble motor of the cardridge was switched on or off
+fn log_rumble(cardridge: &mut Cardridge) {
+    if let Some(rumble) = cardridge.take_rumble_event() {
+        println!("rumble {}", if rumble { "on" } else { "off" });
+    }
+}
+
+/// run the given amount of frames as fast as possible without a window or an audio device,
+/// the battery ram isn't loaded or saved and the rtc is frozen so every run is the same
+fn run_headless(options: &Options, frames: usize) {
+    let mut cardridge = get_cardridge(options.rom.as_deref());
+    cardridge.set_rtc_clock(Box::new(FixedClock(0)));
+    let mut cpu = cpu::Cpu::new(cardridge);
+    let recording_path = get_recording_path(options);
+    if options.wav.is_some() {
+        start_recording(&mut cpu.memory_map.apu, options, &recording_path);
+    }
+    for _ in 0..frames {
+        cpu.start_cycle();
+        cpu.memory_map.apu.take_samples();
+        log_rumble(&mut cpu.memory_map.cardridge);
     }
+    stop_recording(&mut cpu.memory_map.apu, &recording_path);
 }
+
 pub fn main() {
-    let vec1:Vec<u8> = get_rom();
-    let mut sdl_help = Sdl2Helper::new();
-    let cardridge = cardridge::Cardridge{
-        memory: vec1,
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}", error);
+            eprintln!("{}", options::USAGE);
+            process::exit(1);
+        }
     };
+    if let Some(frames) = options.headless {
+        return run_headless(&options, frames);
+    }
+    let rom_path = options.rom.as_deref();
+    let mut cardridge = get_cardridge(rom_path);
+    let save_path = get_save_path(&cardridge, rom_path);
+    load_battery(&mut cardridge, &save_path);
+    let mut sdl_help = Sdl2Helper::new();
     let mut cpu = cpu::Cpu::new(cardridge);
+    let colors = sdl_help.settings.color_scheme.get_colors().map(|[r, g, b]| Color::RGB(r, g, b));
+    cpu.memory_map.renderer.set_colors(colors);
+    if let Some(audio) = &sdl_help.audio {
+        cpu.memory_map.apu.set_sample_rate(audio.spec().freq.unsigned_abs());
+    }
 
     let mut buttons: u8 = 0x0f;
     let mut d_pad: u8 = 0x0f;
-
+    let mut frame: usize = 0;
+    let mut slot: u8 = 1;
+    let mut rewind = Rewind::new(sdl_help.settings.rewind_size * 1024 * 1024);
+    let mut rewinding = false;
+    let mut pacer = FramePacer::new();
+    let recording_path = get_recording_path(&options);
+    if options.wav.is_some() {
+        start_recording(&mut cpu.memory_map.apu, &options, &recording_path);
+    }
 
     'running: loop {
         sdl_help.add_debug_message(format!("{:#04x}", &cpu.memory_map.get_8bit_full_address(0xff00)).as_str().to_string());
         sdl_help.add_debug_message("hello world".to_owned());
-        cpu.start_cycle();
+        if rewinding {
+            // step back one snapshot every frame while the key is held
+            if let Some(state) = rewind.pop() {
+                if let Err(error) = save_state::load(&mut cpu, &state) {
+                    eprintln!("could not rewind: {}", error);
+                }
+            }
+        } else {
+            cpu.start_cycle();
+            if sdl_help.settings.rewind_size > 0 && frame.is_multiple_of(sdl_help.settings.rewind_interval.max(1)) {
+                rewind.push(save_state::save(&cpu));
+            }
+        }
+
+        let samples = cpu.memory_map.apu.take_samples();
+        sdl_help.queue_audio(&samples);
 
         sdl_help.canvas.clear();
         sdl_help.canvas.set_draw_color(Color::RGB(0, 0, 0));
@@ -149,6 +421,29 @@ pub fn main() {
                 Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
                     break 'running
                 },
+                Event::KeyDown { keycode: Some(Keycode::F2), .. } => {      //save the battery ram
+                    save_battery(&cpu.memory_map.cardridge, &save_path);
+                },
+                Event::KeyDown { keycode: Some(Keycode::F5), .. } => {      //save state
+                    save_state(&cpu, rom_path, slot);
+                },
+                Event::KeyDown { keycode: Some(Keycode::F8), .. } => {      //load state
+                    load_state(&mut cpu, rom_path, slot);
+                    rewind.clear();
+                },
+                Event::KeyDown { keycode: Some(Keycode::F9), .. } => {      //start or stop recording audio
+                    if cpu.memory_map.apu.is_recording() {
+                        stop_recording(&mut cpu.memory_map.apu, &recording_path);
+                    } else {
+                        start_recording(&mut cpu.memory_map.apu, &options, &recording_path);
+                    }
+                },
+                Event::KeyDown { keycode: Some(Keycode::Backspace), .. } => { //rewind
+                    rewinding = true;
+                },
+                Event::KeyUp { keycode: Some(Keycode::Backspace), .. } => {
+                    rewinding = false;
+                },
                 Event::KeyDown { keycode: Some(Keycode::Z), .. } => {       //A
                     buttons &= 0xfe;
                 },
@@ -197,16 +492,32 @@ pub fn main() {
                 Event::KeyUp { keycode: Some(Keycode::Down), .. } => {      //DOWN
                     d_pad |= 8;
                 },
+                Event::KeyDown { keycode: Some(keycode), .. } if get_slot(keycode).is_some() => {
+                    slot = get_slot(keycode).unwrap_or(slot);
+                    println!("selected save state slot {}", slot);
+                },
                 
                 _ => {}
             }
         }
         cpu.memory_map.store_buttons(buttons);
         cpu.memory_map.store_d_pad(d_pad);
+        log_rumble(&mut cpu.memory_map.cardridge);
+        frame += 1;
+        if frame.is_multiple_of(BATTERY_SAVE_FRAMES) && cpu.memory_map.cardridge.take_ram_changed() {
+            save_battery(&cpu.memory_map.cardridge, &save_path);
+        }
         // The rest of the game loop goes here...
         sdl_help.canvas.present();
-        ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
+        // without new samples, while rewinding or stopped, the audio can't pace the frames
+        if sdl_help.settings.audio_sync && !samples.is_empty() && sdl_help.sync_to_audio(&mut cpu.memory_map.apu) {
+            pacer.reset();
+        } else {
+            pacer.wait_for_frame();
+        }
     }
+    save_battery(&cpu.memory_map.cardridge, &save_path);
+    stop_recording(&mut cpu.memory_map.apu, &recording_path);
 }
 
 
@@ -0,0 +1,507 @@
+use serde::{Deserialize, Serialize};
+
+use crate::rtc::{Rtc, SystemClock};
+
+/// the memory bank controller of a cardridge, selected by the cardridge type in the header
+#[derive(Serialize, Deserialize)]
+pub enum Mbc {
+    RomOnly,
+    Mbc1(Mbc1),
+    Mbc2(Mbc2),
+    Mbc3(Mbc3),
+    Mbc5(Mbc5),
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Mbc1 {
+    rom_banks: usize,
+    ram_enabled: bool,
+    rom_bank: u8,
+    upper_bank: u8,
+    advanced_mode: bool,
+}
+
+/// MBC2 has 512 half bytes of ram built in
+#[derive(Serialize, Deserialize)]
+pub struct Mbc2 {
+    rom_banks: usize,
+    ram_enabled: bool,
+    rom_bank: u8,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Mbc3 {
+    rom_banks: usize,
+    ram_enabled: bool,
+    rom_bank: u8,
+    /// 0x00-0x03 select a ram bank, 0x08-0x0c an rtc register
+    ram_select: u8,
+    latch_state: u8,
+    pub rtc: Option<Rtc>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Mbc5 {
+    rom_banks: usize,
+    ram_enabled: bool,
+    rom_bank: u16,
+    ram_bank: u8,
+    has_rumble: bool,
+    rumble: bool,
+    rumble_event: Option<bool>,
+}
+
+impl Mbc {
+    /// returns None for cardridge types that aren't supported
+    pub fn new(cardridge_type: u8, rom_banks: usize) -> Option<Self> {
+        // the bank number is masked to the address lines of the chip, the 72, 80 and 96 bank
+        // roms are wired like 128 bank roms and the banks past their end read as open bus
+        let rom_banks = rom_banks.next_power_of_two();
+        match cardridge_type {
+            0x00 | 0x08 | 0x09 => Some(Mbc::RomOnly),
+            0x01..=0x03        => Some(Mbc::Mbc1(Mbc1::new(rom_banks))),
+            0x05 | 0x06        => Some(Mbc::Mbc2(Mbc2::new(rom_banks))),
+            0x0f | 0x10        => Some(Mbc::Mbc3(Mbc3::new(rom_banks, Some(Rtc::new(Box::new(SystemClock)))))),
+            0x11..=0x13        => Some(Mbc::Mbc3(Mbc3::new(rom_banks, None))),
+            0x19..=0x1b        => Some(Mbc::Mbc5(Mbc5::new(rom_banks, false))),
+            0x1c..=0x1e        => Some(Mbc::Mbc5(Mbc5::new(rom_banks, true))),
+            _ => None,
+        }
+    }
+
+    /// writes to 0x0000-0x7fff go to the bank controller registers
+    pub fn write(&mut self, location: usize, value: u8) {
+        match self {
+            Mbc::RomOnly => (),
+            Mbc::Mbc1(mbc) => mbc.write(location, value),
+            Mbc::Mbc2(mbc) => mbc.write(location, value),
+            Mbc::Mbc3(mbc) => mbc.write(location, value),
+            Mbc::Mbc5(mbc) => mbc.write(location, value),
+        }
+    }
+
+    /// the size of the external ram, MBC2 has its ram built in so the header says 0
+    pub fn get_ram_size(&self, header_ram_size: usize) -> usize {
+        match self {
+            Mbc::Mbc2(_) => 0x200,
+            _ => header_ram_size,
+        }
+    }
+
+    /// the offset in the rom for an address in 0x0000-0x7fff
+    pub fn get_rom_address(&self, location: usize) -> usize {
+        match self {
+            Mbc::RomOnly => location,
+            Mbc::Mbc1(mbc) => mbc.get_rom_address(location),
+            Mbc::Mbc2(mbc) => mbc.get_rom_address(location),
+            Mbc::Mbc3(mbc) => mbc.get_rom_address(location),
+            Mbc::Mbc5(mbc) => mbc.get_rom_address(location),
+        }
+    }
+
+    /// read from the external ram at 0xa000-0xbfff, disabled or missing ram reads 0xff
+    pub fn read_ram(&self, ram: &[u8], location: usize) -> u8 {
+        if let Mbc::Mbc3(mbc) = self {
+            if let Some(rtc) = mbc.get_selected_rtc() {
+                return rtc.read(mbc.ram_select);
+            }
+        }
+        // MBC2 only stores the lower 4 bits, the upper bits read as 1
+        let unused_bits = if let Mbc::Mbc2(_) = self { 0xf0 } else { 0x00 };
+        match self.get_ram_address(location) {
+            Some(address) if !ram.is_empty() => ram[address % ram.len()] | unused_bits,
+            _ => 0xff,
+        }
+    }
+
+    /// returns true if a byte was stored in the ram or the write changed the rtc
+    pub fn write_ram(&mut self, ram: &mut [u8], location: usize, value: u8) -> bool {
+        if let Mbc::Mbc3(mbc) = self {
+            let register = mbc.ram_select;
+            if let Some(rtc) = mbc.get_selected_rtc_mut() {
+                return rtc.write(register, value);
+            }
+        }
+        if let Some(address) = self.get_ram_address(location) {
+            if !ram.is_empty() {
+                let length = ram.len();
+                ram[address % length] = if let Mbc::Mbc2(_) = self { value & 0x0f } else { value };
+                return true;
+            }
+        }
+        false
+    }
+
+    /// the offset in the external ram for an address in 0xa000-0xbfff, None if the ram is disabled
+    fn get_ram_address(&self, location: usize) -> Option<usize> {
+        match self {
+            Mbc::RomOnly => Some(location - 0xa000),
+            Mbc::Mbc1(mbc) => mbc.get_ram_address(location),
+            Mbc::Mbc2(mbc) => mbc.get_ram_address(location),
+            Mbc::Mbc3(mbc) => mbc.get_ram_address(location),
+            Mbc::Mbc5(mbc) => mbc.get_ram_address(location),
+        }
+    }
+
+    /// returns the new rumble motor state if it changed since the last call
+    pub fn take_rumble_event(&mut self) -> Option<bool> {
+        match self {
+            Mbc::Mbc5(mbc) => mbc.rumble_event.take(),
+            _ => None,
+        }
+    }
+
+    pub fn get_rtc(&self) -> Option<&Rtc> {
+        match self {
+            Mbc::Mbc3(mbc) => mbc.rtc.as_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn get_rtc_mut(&mut self) -> Option<&mut Rtc> {
+        match self {
+            Mbc::Mbc3(mbc) => mbc.rtc.as_mut(),
+            _ => None,
+        }
+    }
+}
+
+impl Mbc1 {
+    pub fn new(rom_banks: usize) -> Self {
+        Mbc1 {
+            rom_banks,
+            ram_enabled: false,
+            rom_bank: 1,
+            upper_bank: 0,
+            advanced_mode: false,
+        }
+    }
+
+    fn write(&mut self, location: usize, value: u8) {
+        match location {
+            0x0000..=0x1fff => self.ram_enabled = value & 0x0f == 0x0a,
+            // bank 0 can't be selected in the switchable area, it's read as bank 1
+            0x2000..=0x3fff => self.rom_bank = if value & 0x1f == 0 { 1 } else { value & 0x1f },
+            0x4000..=0x5fff => self.upper_bank = value & 0x03,
+            _               => self.advanced_mode = value & 0x01 == 0x01,
+        }
+    }
+
+    fn get_rom_address(&self, location: usize) -> usize {
+        let bank = match location {
+            // in advanced mode the upper bits also switch the first area
+            0x0000..=0x3fff if self.advanced_mode => usize::from(self.upper_bank) << 5,
+            0x0000..=0x3fff => 0,
+            _ => usize::from(self.upper_bank) << 5 | usize::from(self.rom_bank),
+        };
+        // bank numbers past the end of the rom wrap around
+        (bank & (self.rom_banks - 1)) * 0x4000 + (location & 0x3fff)
+    }
+
+    fn get_ram_address(&self, location: usize) -> Option<usize> {
+        if !self.ram_enabled {
+            return None;
+        }
+        let bank = if self.advanced_mode { usize::from(self.upper_bank) } else { 0 };
+        Some(bank * 0x2000 + (location - 0xa000))
+    }
+}
+
+impl Mbc2 {
+    pub fn new(rom_banks: usize) -> Self {
+        Mbc2 {
+            rom_banks,
+            ram_enabled: false,
+            rom_bank: 1,
+        }
+    }
+
+    /// bit 8 of the address selects between the ram enable and rom bank register
+    fn write(&mut self, location: usize, value: u8) {
+        match location {
+            0x0000..=0x3fff if location & 0x100 == 0 => self.ram_enabled = value & 0x0f == 0x0a,
+            0x0000..=0x3fff => self.rom_bank = if value & 0x0f == 0 { 1 } else { value & 0x0f },
+            _ => (),
+        }
+    }
+
+    fn get_rom_address(&self, location: usize) -> usize {
+        let bank = match location {
+            0x0000..=0x3fff => 0,
+            _ => usize::from(self.rom_bank),
+        };
+        (bank & (self.rom_banks - 1)) * 0x4000 + (location & 0x3fff)
+    }
+
+    /// the 512 bytes of ram are repeated over the whole ram area
+    fn get_ram_address(&self, location: usize) -> Option<usize> {
+        if !self.ram_enabled {
+            return None;
+        }
+        Some((location - 0xa000) & 0x1ff)
+    }
+}
+
+impl Mbc3 {
+    pub fn new(rom_banks: usize, rtc: Option<Rtc>) -> Self {
+        Mbc3 {
+            rom_banks,
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_select: 0,
+            latch_state: 0xff,
+            rtc,
+        }
+    }
+
+    fn write(&mut self, location: usize, value: u8) {
+        match location {
+            0x0000..=0x1fff => self.ram_enabled = value & 0x0f == 0x0a,
+            0x2000..=0x3fff => self.rom_bank = if value & 0x7f == 0 { 1 } else { value & 0x7f },
+            0x4000..=0x5fff => self.ram_select = value,
+            _               => {
+                // writing 0 and then 1 latches the clock
+                if self.latch_state == 0x00 && value == 0x01 {
+                    if let Some(rtc) = self.rtc.as_mut() {
+                        rtc.latch();
+                    }
+                }
+                self.latch_state = value;
+            },
+        }
+    }
+
+    fn get_rom_address(&self, location: usize) -> usize {
+        let bank = match location {
+            0x0000..=0x3fff => 0,
+            _ => usize::from(self.rom_bank),
+        };
+        (bank & (self.rom_banks - 1)) * 0x4000 + (location & 0x3fff)
+    }
+
+    fn get_ram_address(&self, location: usize) -> Option<usize> {
+        if !self.ram_enabled || self.ram_select > 0x03 {
+            return None;
+        }
+        Some(usize::from(self.ram_select) * 0x2000 + (location - 0xa000))
+    }
+
+    fn get_selected_rtc(&self) -> Option<&Rtc> {
+        match self.ram_select {
+            0x08..=0x0c if self.ram_enabled => self.rtc.as_ref(),
+            _ => None,
+        }
+    }
+
+    fn get_selected_rtc_mut(&mut self) -> Option<&mut Rtc> {
+        match self.ram_select {
+            0x08..=0x0c if self.ram_enabled => self.rtc.as_mut(),
+            _ => None,
+        }
+    }
+}
+
+impl Mbc5 {
+    pub fn new(rom_banks: usize, has_rumble: bool) -> Self {
+        Mbc5 {
+            rom_banks,
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            has_rumble,
+            rumble: false,
+            rumble_event: None,
+        }
+    }
+
+    fn write(&mut self, location: usize, value: u8) {
+        match location {
+            0x0000..=0x1fff => self.ram_enabled = value & 0x0f == 0x0a,
+            0x2000..=0x2fff => self.rom_bank = (self.rom_bank & 0x100) | u16::from(value),
+            0x3000..=0x3fff => self.rom_bank = (self.rom_bank & 0xff) | (u16::from(value & 0x01) << 8),
+            // on rumble cardridges bit 3 drives the motor instead of selecting a ram bank
+            0x4000..=0x5fff if self.has_rumble => {
+                self.ram_bank = value & 0x07;
+                let rumble = value & 0x08 == 0x08;
+                if rumble != self.rumble {
+                    self.rumble = rumble;
+                    self.rumble_event = Some(rumble);
+                }
+            },
+            0x4000..=0x5fff => self.ram_bank = value & 0x0f,
+            _ => (),
+        }
+    }
+
+    /// unlike MBC1 and MBC3 bank 0 can be mapped to the switchable area
+    fn get_rom_address(&self, location: usize) -> usize {
+        let bank = match location {
+            0x0000..=0x3fff => 0,
+            _ => usize::from(self.rom_bank),
+        };
+        (bank & (self.rom_banks - 1)) * 0x4000 + (location & 0x3fff)
+    }
+
+    fn get_ram_address(&self, location: usize) -> Option<usize> {
+        if !self.ram_enabled {
+            return None;
+        }
+        Some(usize::from(self.ram_bank) * 0x2000 + (location - 0xa000))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtc::FixedClock;
+
+    #[test]
+    fn test_mbc1_rom_bank() -> Result<(), String> {
+        let mut mbc = Mbc::new(0x01, 0x80).ok_or("no mbc")?;
+        assert_eq!(mbc.get_rom_address(0x4000), 0x4000);
+        mbc.write(0x2000, 0x05);
+        assert_eq!(mbc.get_rom_address(0x4123), 0x14123);
+        assert_eq!(mbc.get_rom_address(0x0123), 0x0123);
+        mbc.write(0x2000, 0x00);
+        assert_eq!(mbc.get_rom_address(0x4000), 0x4000);
+        mbc.write(0x2000, 0x20);
+        assert_eq!(mbc.get_rom_address(0x4000), 0x4000);
+        let mut small = Mbc::new(0x01, 4).ok_or("no mbc")?;
+        small.write(0x2000, 0x06);
+        assert_eq!(small.get_rom_address(0x4000), 0x8000);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mbc1_upper_bank() -> Result<(), String> {
+        let mut mbc = Mbc::new(0x01, 0x80).ok_or("no mbc")?;
+        mbc.write(0x4000, 0x01);
+        mbc.write(0x2000, 0x02);
+        assert_eq!(mbc.get_rom_address(0x4000), 0x22 * 0x4000);
+        assert_eq!(mbc.get_rom_address(0x0000), 0);
+        mbc.write(0x6000, 0x01);
+        assert_eq!(mbc.get_rom_address(0x0000), 0x20 * 0x4000);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mbc1_ram() -> Result<(), String> {
+        let mut mbc = Mbc::new(0x03, 0x80).ok_or("no mbc")?;
+        let mut ram = vec![0; 0x8000];
+        mbc.write_ram(&mut ram, 0xa000, 0x12);
+        assert_eq!(mbc.read_ram(&ram, 0xa000), 0xff);
+        assert_eq!(ram[0], 0);
+        assert_eq!(mbc.get_ram_address(0xa000), None);
+        mbc.write(0x0000, 0x0a);
+        assert_eq!(mbc.get_ram_address(0xa010), Some(0x10));
+        mbc.write(0x4000, 0x02);
+        assert_eq!(mbc.get_ram_address(0xa010), Some(0x10));
+        mbc.write(0x6000, 0x01);
+        assert_eq!(mbc.get_ram_address(0xa010), Some(0x4010));
+        mbc.write_ram(&mut ram, 0xa010, 0x34);
+        assert_eq!(ram[0x4010], 0x34);
+        assert_eq!(mbc.read_ram(&ram, 0xa010), 0x34);
+        mbc.write(0x1000, 0x00);
+        assert_eq!(mbc.get_ram_address(0xa010), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mbc3_rom_and_ram_bank() -> Result<(), String> {
+        let mut mbc = Mbc::new(0x13, 0x80).ok_or("no mbc")?;
+        mbc.write(0x2000, 0x45);
+        assert_eq!(mbc.get_rom_address(0x4001), 0x45 * 0x4000 + 1);
+        mbc.write(0x2000, 0x00);
+        assert_eq!(mbc.get_rom_address(0x4000), 0x4000);
+        let mut ram = vec![0; 0x8000];
+        mbc.write(0x0000, 0x0a);
+        mbc.write(0x4000, 0x03);
+        mbc.write_ram(&mut ram, 0xa001, 0x77);
+        assert_eq!(ram[0x6001], 0x77);
+        mbc.write(0x4000, 0x08);
+        assert_eq!(mbc.read_ram(&ram, 0xa001), 0xff);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mbc3_rtc_registers() -> Result<(), String> {
+        let mut mbc3 = Mbc3::new(0x80, Some(Rtc::new(Box::new(FixedClock(0)))));
+        mbc3.write(0x0000, 0x0a);
+        mbc3.write(0x4000, 0x09);
+        let mut mbc = Mbc::Mbc3(mbc3);
+        let mut ram = vec![0; 0x2000];
+        mbc.write_ram(&mut ram, 0xa000, 42);
+        assert_eq!(ram[0], 0);
+        assert_eq!(mbc.read_ram(&ram, 0xa000), 0);
+        mbc.write(0x6000, 0x00);
+        mbc.write(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(&ram, 0xa000), 42);
+        mbc.write_ram(&mut ram, 0xa000, 43);
+        mbc.write(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(&ram, 0xa000), 42);
+        mbc.write(0x6000, 0x00);
+        mbc.write(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(&ram, 0xa000), 43);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mbc2() -> Result<(), String> {
+        let mut mbc = Mbc::new(0x06, 16).ok_or("no mbc")?;
+        let mut ram = vec![0; mbc.get_ram_size(0)];
+        assert_eq!(ram.len(), 0x200);
+        mbc.write(0x2100, 0x03);
+        assert_eq!(mbc.get_rom_address(0x4000), 0xc000);
+        mbc.write(0x0100, 0x0a);
+        assert_eq!(mbc.read_ram(&ram, 0xa000), 0xff);
+        mbc.write(0x0000, 0x0a);
+        mbc.write_ram(&mut ram, 0xa005, 0xab);
+        assert_eq!(ram[5], 0x0b);
+        assert_eq!(mbc.read_ram(&ram, 0xa005), 0xfb);
+        assert_eq!(mbc.read_ram(&ram, 0xa205), 0xfb);
+        mbc.write(0x2100, 0x00);
+        assert_eq!(mbc.get_rom_address(0x4000), 0x4000);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mbc5_banks() -> Result<(), String> {
+        let mut mbc = Mbc::new(0x1b, 0x200).ok_or("no mbc")?;
+        mbc.write(0x2000, 0x00);
+        assert_eq!(mbc.get_rom_address(0x4000), 0x0000);
+        mbc.write(0x2000, 0x23);
+        mbc.write(0x3000, 0x01);
+        assert_eq!(mbc.get_rom_address(0x4000), 0x123 * 0x4000);
+        let mut ram = vec![0; 0x20000];
+        mbc.write(0x0000, 0x0a);
+        mbc.write(0x4000, 0x0f);
+        mbc.write_ram(&mut ram, 0xa000, 0x42);
+        assert_eq!(ram[0x1e000], 0x42);
+        assert_eq!(mbc.take_rumble_event(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mbc5_rumble() -> Result<(), String> {
+        let mut mbc = Mbc::new(0x1e, 0x200).ok_or("no mbc")?;
+        mbc.write(0x4000, 0x09);
+        assert_eq!(mbc.take_rumble_event(), Some(true));
+        assert_eq!(mbc.take_rumble_event(), None);
+        mbc.write(0x4000, 0x0a);
+        assert_eq!(mbc.take_rumble_event(), None);
+        mbc.write(0x4000, 0x02);
+        assert_eq!(mbc.take_rumble_event(), Some(false));
+        mbc.write(0x0000, 0x0a);
+        let mut ram = vec![0; 0x8000];
+        mbc.write_ram(&mut ram, 0xa000, 0x42);
+        assert_eq!(ram[0x4000], 0x42);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unsupported_type() -> Result<(), String> {
+        assert!(Mbc::new(0xfc, 2).is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+use std::path::PathBuf;
+
+use crate::apu::SAMPLE_RATE;
+
+pub const USAGE: &str = "usage: rustboy [rom] [--wav file] [--wav-channels] [--wav-rate hz] [--headless frames]";
+
+/// the command line arguments
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub rom: Option<PathBuf>,
+    /// the audio is recorded from the start to this file
+    pub wav: Option<PathBuf>,
+    /// every channel is also recorded to a file of its own
+    pub wav_channels: bool,
+    pub wav_rate: u32,
+    /// run this many frames without a window or an audio device, then exit
+    pub headless: Option<usize>,
+}
+
+impl Options {
+    /// the arguments without the program name
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Options {
+            rom: None,
+            wav: None,
+            wav_channels: false,
+            wav_rate: SAMPLE_RATE,
+            headless: None,
+        };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--wav" => options.wav = Some(PathBuf::from(get_value(&mut args, &arg)?)),
+                "--wav-channels" => options.wav_channels = true,
+                "--wav-rate" => options.wav_rate = get_number(&mut args, &arg)?,
+                "--headless" => options.headless = Some(get_number(&mut args, &arg)?),
+                _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+                _ if options.rom.is_none() => options.rom = Some(PathBuf::from(arg)),
+                _ => return Err(format!("unexpected argument {}", arg)),
+            }
+        }
+        if options.wav_rate == 0 {
+            return Err("--wav-rate can't be 0".to_string());
+        }
+        Ok(options)
+    }
+}
+
+fn get_value(args: &mut impl Iterator<Item = String>, option: &str) -> Result<String, String> {
+    args.next().ok_or(format!("{} needs a value", option))
+}
+
+fn get_number<T: std::str::FromStr>(args: &mut impl Iterator<Item = String>, option: &str) -> Result<T, String> {
+    let value = get_value(args, option)?;
+    value.parse().map_err(|_| format!("{} needs a number, not {}", option, value))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse() -> Result<(), String> {
+        let options = parse(&["game.gb", "--wav", "music.wav", "--wav-channels", "--wav-rate", "44100", "--headless", "600"])?;
+        assert_eq!(options, Options {
+            rom: Some(PathBuf::from("game.gb")),
+            wav: Some(PathBuf::from("music.wav")),
+            wav_channels: true,
+            wav_rate: 44_100,
+            headless: Some(600),
+        });
+        let options = parse(&[])?;
+        assert_eq!(options.rom, None);
+        assert_eq!(options.wav_rate, SAMPLE_RATE);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_errors() -> Result<(), String> {
+        assert!(parse(&["--wav"]).is_err());
+        assert!(parse(&["--headless", "ten"]).is_err());
+        assert!(parse(&["--wav-rate", "0"]).is_err());
+        assert!(parse(&["--volume"]).is_err());
+        assert!(parse(&["game.gb", "other.gb"]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::apu::CPU_FREQUENCY;
+use crate::renderer::FRAME_CYCLES;
+
+/// a frame of the DMG takes 70224 T-cycles, about 16.74 ms or 59.73 Hz
+pub const FRAME_DURATION: Duration =
+    Duration::from_nanos(FRAME_CYCLES as u64 * 1_000_000_000 / CPU_FREQUENCY as u64);
+/// the schedule starts over instead of catching up when it is this many frames behind
+const MAX_FRAMES_BEHIND: u32 = 4;
+/// thread::sleep can oversleep, the rest of the wait is spent spinning
+const SPIN_DURATION: Duration = Duration::from_millis(1);
+/// how far the sample rate may be moved away from the rate of the audio device
+const MAX_RATE_DELTA: f64 = 0.005;
+
+/// paces the emulation to the frame rate of the DMG with a high resolution timer
+pub struct FramePacer {
+    next_frame: Instant,
+}
+
+impl FramePacer {
+    pub fn new() -> Self {
+        FramePacer {
+            next_frame: Instant::now() + FRAME_DURATION,
+        }
+    }
+
+    /// block until the next frame is due
+    pub fn wait_for_frame(&mut self) {
+        let deadline = self.schedule(Instant::now());
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return;
+            }
+            let remaining = deadline - now;
+            if remaining > SPIN_DURATION {
+                thread::sleep(remaining - SPIN_DURATION);
+            } else {
+                std::hint::spin_loop();
+            }
+        }
+    }
+
+    /// start the schedule over, used while something else paces the frames
+    pub fn reset(&mut self) {
+        self.next_frame = Instant::now() + FRAME_DURATION;
+    }
+
+    /// returns when the current frame ends and moves the schedule to the next frame,
+    /// the deadlines are added up so the frame rate doesn't drift with the time a frame takes
+    fn schedule(&mut self, now: Instant) -> Instant {
+        if now > self.next_frame + FRAME_DURATION * MAX_FRAMES_BEHIND {
+            self.next_frame = now;
+        }
+        let deadline = self.next_frame;
+        self.next_frame += FRAME_DURATION;
+        deadline
+    }
+}
+
+/// dynamic rate control, the apu produces a little more audio while the queue is below
+/// the target and a little less while it is above, so the queue neither runs dry nor grows
+pub fn get_dynamic_sample_rate(sample_rate: u32, queued: u32, target: u32) -> u32 {
+    let fill = f64::from(queued) / f64::from(target.max(1));
+    let adjustment = (1.0 - fill).clamp(-1.0, 1.0) * MAX_RATE_DELTA;
+    (f64::from(sample_rate) * (1.0 + adjustment)).round() as u32
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_duration() -> Result<(), String> {
+        assert_eq!(FRAME_DURATION.as_nanos(), 16_742_706);
+        Ok(())
+    }
+
+    #[test]
+    fn test_schedule() -> Result<(), String> {
+        let start = Instant::now();
+        let mut pacer = FramePacer { next_frame: start };
+        assert_eq!(pacer.schedule(start), start);
+        // a slow frame doesn't move the following deadlines
+        assert_eq!(pacer.schedule(start + FRAME_DURATION * 3 / 2), start + FRAME_DURATION);
+        assert_eq!(pacer.schedule(start + FRAME_DURATION * 3 / 2), start + FRAME_DURATION * 2);
+        // too far behind the schedule starts over
+        let late = start + FRAME_DURATION * 20;
+        assert_eq!(pacer.schedule(late), late);
+        assert_eq!(pacer.schedule(late), late + FRAME_DURATION);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dynamic_sample_rate() -> Result<(), String> {
+        assert_eq!(get_dynamic_sample_rate(48_000, 1000, 1000), 48_000);
+        assert_eq!(get_dynamic_sample_rate(48_000, 0, 1000), 48_240);
+        assert_eq!(get_dynamic_sample_rate(48_000, 1500, 1000), 47_880);
+        assert_eq!(get_dynamic_sample_rate(48_000, 5000, 1000), 47_760);
+        Ok(())
+    }
+}
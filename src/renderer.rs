@@ -1,71 +1,106 @@
 use sdl2::libc::MAP_SHARED;
 use sdl2::pixels::Color;
-use std::cmp::Ordering;
-use std::fmt::{Debug, Error};
+use serde::{Deserialize, Serialize};
+use serde_big_array::BigArray;
+use std::fmt::Debug;
 
 pub const WIDTH: i32 = 160;
 pub const HEIGHT: i32 = 144;
 pub const DEBUG: i32 = 30;
 
+/// the dots (T-cycles) of one line and the length of the modes on a line
+const LINE_DOTS: usize = 456;
+const OAM_SEARCH_DOTS: usize = 80;
+const PIXEL_TRANSFER_DOTS: usize = 172;
+/// 144 visible lines followed by 10 lines of VBlank
+const LINES: u8 = 154;
+/// the T-cycles of a whole frame, 59.73 frames are drawn every second
+pub const FRAME_CYCLES: usize = LINE_DOTS * LINES as usize;
+/// the ppu only draws the first 10 sprites it finds on a line
+const SPRITES_PER_LINE: usize = 10;
+
+const COLORS: [Color; 4] = [
+    Color::WHITE,
+    Color::RGB(169, 169, 169),
+    Color::RGB(105, 105, 105),
+    Color::BLACK,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamSearch = 2,
+    PixelTransfer = 3,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct Renderer {
-    tile_data: [u8; 0x9fff - 0x8000],
+    #[serde(with = "BigArray")]
+    tile_data: [u8; 0xa000 - 0x8000],
+    #[serde(with = "BigArray")]
     oam_data: [u8; 0xfea0 - 0xfe00],
     lcdc: u8,
+    /// only the interrupt select bits 3-6 are stored, the mode and coincidence bits are calculated
+    stat: u8,
+    ly: u8,
+    lyc: u8,
+    scy: u8,
+    scx: u8,
+    wy: u8,
+    wx: u8,
+    /// the palettes map the color numbers of the background and both sprite palettes to shades
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    /// the line of the window that is drawn next, it only advances on lines the window is visible
+    window_line: u8,
+    /// set once LY reached WY in the current frame
+    window_y_reached: bool,
+    mode: Mode,
+    /// the dot within the current line
+    dots: usize,
+    /// the STAT interrupt is only requested when this goes from low to high
+    stat_line: bool,
+    /// the shades of the finished lines
+    screen: Vec<u8>,
+    /// the output color of each shade, this is a setting and not part of the machine state
+    #[serde(skip, default = "get_colors")]
     color: [Color; 4],
 }
-#[derive(Debug)]
-pub struct ColorPosition {
-    pub y: i32,
-    pub x: i32,
 
-    pub color: Color,
-}
-
-impl ColorPosition {
-    fn new(x: i32, y: i32, color: Color) -> Self {
-        ColorPosition { y, x, color }
-    }
+fn get_colors() -> [Color; 4] {
+    COLORS
 }
 
+/// an entry of the object attribute memory
 struct Sprite {
     x: i32,
     y: i32,
-    tile: Tile,
+    tile: u8,
     flags: u8,
 }
 
 impl Sprite {
-    pub fn new(x: i32, y: i32, tile: Tile, flags: u8) -> Self {
-        Sprite { x, y, tile, flags }
-    }
-
-    fn compare(&self, x: i32, y: i32, compare_sprite: &Sprite) -> Ordering {
-        if self.get_pixel(x, y).eq(&Color::WHITE) {
-            Ordering::Less
-        } 
-        else if self.x > compare_sprite.x {
-            Ordering::Less
-        }
-        else {
-            Ordering::Greater
+    /// the first byte in oam is Y, the second is X
+    fn new(oam_entry: &[u8]) -> Self {
+        Sprite {
+            x: i32::from(oam_entry[1]) - 8,
+            y: i32::from(oam_entry[0]) - 16,
+            tile: oam_entry[2],
+            flags: oam_entry[3],
         }
     }
 
-    fn compare_bool(&self, x: i32, y: i32, eight_by_sixteen: bool) -> bool {
-        let y_size = if eight_by_sixteen { 16 } else { 8 };
-        if self.x <= x && self.x + 8 > x && self.y <= y && self.y + y_size > y {
-            true
-        } else {
-            false
-        }
+    fn covers_line(&self, line: i32, height: i32) -> bool {
+        self.y <= line && self.y + height > line
     }
 
-    fn get_pixel(&self, x: i32, y: i32) -> Color {
-        let y_location = usize::try_from(y - self.y).unwrap();
-        let x_location = usize::try_from(x - self.x).unwrap();
-        self.tile.pixels[y_location][x_location]
+    fn covers_x(&self, x: i32) -> bool {
+        self.x <= x && self.x + 8 > x
     }
 
+    /// the background and window colors 1-3 are drawn over the sprite
     fn over_background_foreground(&self) -> bool {
         (self.flags & 0x80) == 0x80
     }
@@ -78,195 +113,374 @@ impl Sprite {
         (self.flags & 0x20) == 0x20
     }
 
+    /// false is OBP0 true is OBP1
     fn palette_number(&self) -> bool {
         (self.flags & 0x10) == 0x10
     }
 }
 
-struct Tile {
-    pixels: [[Color; 8]; 8],
-}
-
-impl Clone for Tile {
-    fn clone(&self) -> Self {
-        Self {
-            pixels: self.pixels.clone(),
-        }
-    }
-}
-
-impl Copy for Tile {}
+#[derive(Debug)]
+pub struct ColorPosition {
+    pub y: i32,
+    pub x: i32,
 
-impl Tile {
-    pub fn new() -> Self {
-        let color = Color::WHITE;
-        Tile {
-            pixels: [[color; 8]; 8],
-        }
-    }
+    pub color: Color,
 }
 
 impl Renderer {
     pub fn new() -> Self {
-        let tile: [u8; 0x9fff - 0x8000] = [0; 0x9fff - 0x8000];
+        let tile: [u8; 0xa000 - 0x8000] = [0; 0xa000 - 0x8000];
         let oam: [u8; 0xfea0 - 0xfe00] = [0; 0xfea0 - 0xfe00];
-        let renderer = Renderer {
+        Renderer {
             tile_data: tile,
             oam_data: oam,
-            lcdc: 0,
-            color: [
-                Color::WHITE,
-                Color::RGB(169, 169, 169),
-                Color::RGB(105, 105, 105),
-                Color::BLACK,
-            ],
-        };
-
-        renderer.get_all_sprites();
-        renderer
+            // the value the boot rom leaves behind
+            lcdc: 0x91,
+            stat: 0,
+            ly: 0,
+            lyc: 0,
+            scy: 0,
+            scx: 0,
+            wy: 0,
+            wx: 0,
+            bgp: 0xfc,
+            obp0: 0xff,
+            obp1: 0xff,
+            window_line: 0,
+            window_y_reached: false,
+            mode: Mode::OamSearch,
+            dots: 0,
+            stat_line: false,
+            screen: vec![0; (WIDTH * HEIGHT) as usize],
+            color: COLORS,
+        }
     }
 
     pub fn store(&mut self, location: usize, value: u8) {
         self.tile_data[location - 0x8000] = value;
     }
 
-    fn get_byte_from_location(&self, location: usize) -> u8 {
-        self.tile_data[(location - 0x8000) % self.tile_data.len()]
+    pub fn read(&self, location: usize) -> u8 {
+        self.get_byte_from_location(location)
     }
 
-    pub fn set_lcdc(&mut self, value: u8) {
-        self.lcdc = value;
+    pub fn read_oam(&self, location: usize) -> u8 {
+        self.oam_data[location - 0xfe00]
     }
 
-    pub fn get_screen(&self) -> Vec<ColorPosition> {
-        let mut screen: Vec<ColorPosition> = Vec::new();
-        let background = self.get_background();
-        let window = self.get_window();
-        for y in 0..=HEIGHT {
-            for x in 0..=WIDTH {
-                let tile: Tile =
-                    background[usize::try_from(y / 8).unwrap()][usize::try_from(x / 8).unwrap()];
-                let color =
-                    tile.pixels[usize::try_from(y % 8).unwrap()][usize::try_from(x % 8).unwrap()];
-                
-                let window_pixel = self.get_window_pixel(window, y, x);
-                let color_position = match window_pixel {
-                    Ok(pixel) => pixel,
-                    Err(_) => ColorPosition { y, x, color }
-                };
-                screen.push(color_position);
-            }
-        }
-        let colors_from_sprite = self.get_sprites_from_screen();
-        for pixel in colors_from_sprite {
-            if !pixel.color.eq(&Color::WHITE) {
-                screen.push(pixel);
-            }
-        }
+    pub fn store_oam(&mut self, location: usize, value: u8) {
+        self.oam_data[location - 0xfe00] = value;
+    }
 
-        screen
+    /// the cpu can't reach vram while a line is drawn
+    pub fn vram_accessible(&self) -> bool {
+        !self.get_lcd_enable() || self.mode != Mode::PixelTransfer
     }
 
-    fn get_window_pixel(&self, window: [[Tile; 32]; 32], y: i32, x: i32) -> Result<ColorPosition, Error> {
-        let tile: Tile = window[usize::try_from(y / 8).unwrap()][usize::try_from(x / 8).unwrap()];
-        let color = tile.pixels[usize::try_from(y % 8).unwrap()][usize::try_from(x % 8).unwrap()];
-        Ok(ColorPosition { y, x, color })
-    }
-
-    fn get_sprites_from_screen(&self) -> Vec<ColorPosition> {
-        let eight_by_sixteen = self.get_object_size();
-        let mut screen: Vec<ColorPosition> = Vec::new();
-        let sprites = self.get_all_sprites();
-        for y in -16..=HEIGHT {
-            let mut pixel_count = 0;
-            for x in -8..=WIDTH {
-                let found_sprites: Vec<&Sprite> = sprites
-                    .iter()
-                    .filter(|sprite| sprite.compare_bool(x, y, eight_by_sixteen))
-                    .collect();
-                if found_sprites.len() == 0 {
-                    continue;
+    /// the cpu can't reach oam while the ppu searches or draws sprites
+    pub fn oam_accessible(&self) -> bool {
+        !self.get_lcd_enable() || !matches!(self.mode, Mode::OamSearch | Mode::PixelTransfer)
+    }
+
+    fn get_byte_from_location(&self, location: usize) -> u8 {
+        self.tile_data[location - 0x8000]
+    }
+
+    /// advance the ppu by the given amount of dots,
+    /// returns if the VBlank and the STAT interrupt should be requested
+    pub fn tick(&mut self, cycles: usize) -> (bool, bool) {
+        let mut vblank = false;
+        let mut stat = false;
+        if !self.get_lcd_enable() {
+            return (vblank, stat);
+        }
+        for _ in 0..cycles {
+            self.dots += 1;
+            if self.ly < HEIGHT as u8 {
+                if self.dots == OAM_SEARCH_DOTS {
+                    self.mode = Mode::PixelTransfer;
+                } else if self.dots == OAM_SEARCH_DOTS + PIXEL_TRANSFER_DOTS {
+                    self.render_line();
+                    self.mode = Mode::HBlank;
                 }
-                if pixel_count >= 10 {
-                    break;
+            }
+            if self.dots == LINE_DOTS {
+                self.dots = 0;
+                self.ly = (self.ly + 1) % LINES;
+                if self.ly == 0 {
+                    self.start_frame();
                 }
-                pixel_count += 1;
-                if x < 0 || y < 0 {
-                    continue;
+                if self.ly == HEIGHT as u8 {
+                    self.mode = Mode::VBlank;
+                    vblank = true;
+                } else if self.ly < HEIGHT as u8 {
+                    self.mode = Mode::OamSearch;
                 }
-                let sprite = *found_sprites
-                                    .iter()
-                                    .max_by(|s1, s2| s1.compare(x, y, *s2))
-                                    .unwrap();
-                screen.push(ColorPosition::new(x, y, sprite.get_pixel(x, y)));
             }
+            stat |= self.update_stat_line();
         }
-        screen
+        (vblank, stat)
     }
 
-    fn get_all_sprites(&self) -> Vec<Sprite> {
-        let mut sprites: Vec<Sprite> = Vec::new();
-        for i in 0..40 {
-            sprites.push(self.get_sprite(i));
+    pub fn get_lcdc(&self) -> u8 {
+        self.lcdc
+    }
+
+    /// turning the lcd off resets LY and the mode, turning it on starts a new frame
+    pub fn set_lcdc(&mut self, value: u8) {
+        let was_enabled = self.get_lcd_enable();
+        self.lcdc = value;
+        if was_enabled && !self.get_lcd_enable() {
+            self.ly = 0;
+            self.dots = 0;
+            self.mode = Mode::HBlank;
+            self.start_frame();
+        } else if !was_enabled && self.get_lcd_enable() {
+            self.mode = Mode::OamSearch;
         }
-        sprites.sort_by(|a, b| a.x.cmp(&b.x));
-        sprites
+        self.update_stat_line();
+    }
+
+    /// bit 7 reads as 1, bit 2 is set when LY equals LYC and bits 0-1 hold the mode
+    pub fn get_stat(&self) -> u8 {
+        let coincidence = if self.ly == self.lyc { 0x04 } else { 0 };
+        0x80 | self.stat | coincidence | self.mode as u8
+    }
+
+    /// only the interrupt select bits can be written
+    pub fn set_stat(&mut self, value: u8) {
+        self.stat = value & 0x78;
+        self.update_stat_line();
+    }
+
+    pub fn get_ly(&self) -> u8 {
+        self.ly
+    }
+
+    pub fn get_lyc(&self) -> u8 {
+        self.lyc
+    }
+
+    pub fn set_lyc(&mut self, value: u8) {
+        self.lyc = value;
+        self.update_stat_line();
+    }
+
+    pub fn get_scy(&self) -> u8 {
+        self.scy
     }
 
-    fn get_background(&self) -> [[Tile; 32]; 32] {
-        let data_area = self.get_background_tile_data_area();
-        let map_area = self.get_background_tile_map_area();
-        let map_index = if map_area { 0x9800 } else { 0x9c00 };
-        let data_index: usize = if data_area { 0x8000 } else { 0x8800 };
-        let mut background: [[Tile; 32]; 32] = [[Tile::new(); 32]; 32];
-        for i in 0..0x300 {
-            let tile_number: usize = usize::from(self.get_byte_from_location(i + map_index));
-            let tile_data = self.get_tile(tile_number * 16 + data_index);
-            let x = i % 32;
-            let y = i / 32;
-            background[y][x] = self.convert_tile(tile_data);
+    pub fn set_scy(&mut self, value: u8) {
+        self.scy = value;
+    }
+
+    pub fn get_scx(&self) -> u8 {
+        self.scx
+    }
+
+    pub fn set_scx(&mut self, value: u8) {
+        self.scx = value;
+    }
+
+    pub fn get_wy(&self) -> u8 {
+        self.wy
+    }
+
+    pub fn set_wy(&mut self, value: u8) {
+        self.wy = value;
+    }
+
+    pub fn get_wx(&self) -> u8 {
+        self.wx
+    }
+
+    pub fn set_wx(&mut self, value: u8) {
+        self.wx = value;
+    }
+
+    pub fn get_bgp(&self) -> u8 {
+        self.bgp
+    }
+
+    pub fn set_bgp(&mut self, value: u8) {
+        self.bgp = value;
+    }
+
+    pub fn get_obp0(&self) -> u8 {
+        self.obp0
+    }
+
+    pub fn set_obp0(&mut self, value: u8) {
+        self.obp0 = value;
+    }
+
+    pub fn get_obp1(&self) -> u8 {
+        self.obp1
+    }
+
+    pub fn set_obp1(&mut self, value: u8) {
+        self.obp1 = value;
+    }
+
+    pub fn get_colors(&self) -> [Color; 4] {
+        self.color
+    }
+
+    /// the colors the four shades are drawn with, from lightest to darkest
+    pub fn set_colors(&mut self, colors: [Color; 4]) {
+        self.color = colors;
+    }
+
+    /// returns true when one of the selected STAT conditions became active
+    fn update_stat_line(&mut self) -> bool {
+        let line = (self.stat & 0x40 == 0x40 && self.ly == self.lyc)
+            || (self.stat & 0x20 == 0x20 && self.mode == Mode::OamSearch)
+            || (self.stat & 0x10 == 0x10 && self.mode == Mode::VBlank)
+            || (self.stat & 0x08 == 0x08 && self.mode == Mode::HBlank);
+        let rising = line && !self.stat_line;
+        self.stat_line = line;
+        rising && self.get_lcd_enable()
+    }
+
+    pub fn get_screen(&self) -> Vec<ColorPosition> {
+        let mut screen: Vec<ColorPosition> = Vec::with_capacity(self.screen.len());
+        for (index, shade) in self.screen.iter().enumerate() {
+            let x = index as i32 % WIDTH;
+            let y = index as i32 / WIDTH;
+            let color = self.color[usize::from(*shade)];
+            screen.push(ColorPosition { y, x, color });
         }
+        screen
+    }
+
+    fn start_frame(&mut self) {
+        self.window_line = 0;
+        self.window_y_reached = false;
+    }
 
-        background
-    }
-
-    fn get_window(&self) -> [[Tile; 32]; 32] {
-        let data_area = self.get_background_tile_data_area();
-        let map_area = self.get_window_tile_map_area();
-        let map_index = if map_area { 0x9800 } else { 0x9c00 };
-        let data_index: usize = if data_area { 0x8000 } else { 0x8800 };
-        let mut window: [[Tile; 32]; 32] = [[Tile::new(); 32]; 32];
-        for i in 0..0x300 {
-            let tile_number: usize = usize::from(self.get_byte_from_location(i + map_index));
-            let tile_data = self.get_tile(tile_number * 16 + data_index);
-            let x = i % 32;
-            let y = i / 32;
-            window[y][x] = self.convert_tile(tile_data);
+    /// draw the current line with the register values at the end of pixel transfer
+    fn render_line(&mut self) {
+        let y = usize::from(self.ly);
+        if self.ly == self.wy {
+            self.window_y_reached = true;
+        }
+        let background_map = if self.get_background_tile_map_area() { 0x9c00 } else { 0x9800 };
+        let window_map = if self.get_window_tile_map_area() { 0x9c00 } else { 0x9800 };
+        // the window starts at WX - 7, WX above 166 hides it
+        let window_visible = self.get_window_enable() && self.window_y_reached && self.wx <= 166;
+        let window_start = usize::from(self.wx).saturating_sub(7);
+        let mut window_drawn = false;
+        let mut background_numbers = [0u8; WIDTH as usize];
+        for (x, background_number) in background_numbers.iter_mut().enumerate() {
+            // with the background disabled the line is white whatever BGP holds
+            if !self.get_window_background_priority() {
+                self.screen[y * WIDTH as usize + x] = 0;
+                continue;
+            }
+            let color_number = if window_visible && x + 7 >= usize::from(self.wx) {
+                window_drawn = true;
+                self.get_map_pixel(window_map, x - window_start, usize::from(self.window_line))
+            } else {
+                // the background is 256x256 pixels and wraps around
+                let background_x = (x + usize::from(self.scx)) & 0xff;
+                let background_y = (y + usize::from(self.scy)) & 0xff;
+                self.get_map_pixel(background_map, background_x, background_y)
+            };
+            *background_number = color_number;
+            self.screen[y * WIDTH as usize + x] = Self::get_shade(self.bgp, color_number);
         }
+        if window_drawn {
+            self.window_line += 1;
+        }
+        if self.get_object_enable() {
+            self.render_sprites(&background_numbers);
+        }
+    }
 
-        window
+    /// the first 10 sprites in oam on the line, the one with the lowest X is drawn on top
+    /// and sprites with the same X are ordered by their place in oam
+    fn get_line_sprites(&self) -> Vec<Sprite> {
+        let line = i32::from(self.ly);
+        let height = self.get_object_height();
+        let mut sprites: Vec<Sprite> = self.oam_data
+            .chunks(4)
+            .map(Sprite::new)
+            .filter(|sprite| sprite.covers_line(line, height))
+            .take(SPRITES_PER_LINE)
+            .collect();
+        sprites.sort_by_key(|sprite| sprite.x);
+        sprites
     }
 
-    fn get_sprite(&self, number: usize) -> Sprite {
-        let oam_offset = number * 4;
-        let tile = self.get_tile(<u8 as Into<usize>>::into(self.oam_data[oam_offset + 2]) + 0x8000);
-        let x = i32::from(self.oam_data[oam_offset]) - 8;
-        let y = i32::from(self.oam_data[oam_offset + 1]) - 16;
-        let tile = self.convert_tile(tile);
+    fn render_sprites(&mut self, background_numbers: &[u8]) {
+        let line = i32::from(self.ly);
+        let sprites = self.get_line_sprites();
+        for x in 0..WIDTH {
+            // color 0 of a sprite is transparent so the next sprite can show through
+            let pixel = sprites
+                .iter()
+                .filter(|sprite| sprite.covers_x(x))
+                .map(|sprite| (sprite, self.get_sprite_pixel(sprite, x, line)))
+                .find(|(_, color_number)| *color_number != 0);
+            if let Some((sprite, color_number)) = pixel {
+                let x = x as usize;
+                if sprite.over_background_foreground() && background_numbers[x] != 0 {
+                    continue;
+                }
+                let palette = if sprite.palette_number() { self.obp1 } else { self.obp0 };
+                self.screen[usize::from(self.ly) * WIDTH as usize + x] = Self::get_shade(palette, color_number);
+            }
+        }
+    }
+
+    /// sprites always use the tiles at 0x8000, in 8x16 mode the top tile has the lowest bit cleared
+    fn get_sprite_pixel(&self, sprite: &Sprite, x: i32, line: i32) -> u8 {
+        let height = self.get_object_height();
+        let mut row = line - sprite.y;
+        if sprite.yflip() {
+            row = height - 1 - row;
+        }
+        let mut column = x - sprite.x;
+        if sprite.xflip() {
+            column = 7 - column;
+        }
+        let tile = if height == 16 { sprite.tile & 0xfe } else { sprite.tile };
+        let tile_location = 0x8000 + usize::from(tile) * 16;
+        self.get_tile_pixel(tile_location, column as usize, row as usize)
+    }
 
-        let flags = self.oam_data[oam_offset + 3];
+    /// every 2 bits of a palette hold the shade of a color number, starting with color 0 in bits 0-1
+    fn get_shade(palette: u8, color_number: u8) -> u8 {
+        (palette >> (color_number * 2)) & 0x03
+    }
 
-        Sprite::new(x, y, tile, flags)
+    /// the color number at a pixel of the 32x32 tile map at map_index
+    fn get_map_pixel(&self, map_index: usize, x: usize, y: usize) -> u8 {
+        let tile_number = self.get_byte_from_location(map_index + (y / 8) * 32 + x / 8);
+        let tile_location = self.get_background_tile_location(tile_number);
+        self.get_tile_pixel(tile_location, x % 8, y % 8)
     }
 
-    /// get the next 16 bites that form 1 tile
-    fn get_tile(&self, location: usize) -> [u8; 16] {
-        let mut tile: [u8; 16] = [0; 16];
-        for i in 0..16 {
-            tile[i] = self.get_byte_from_location(i + location);
+    /// area 1 uses the tile number unsigned from 0x8000, area 0 signed from 0x9000
+    fn get_background_tile_location(&self, tile_number: u8) -> usize {
+        if self.get_background_tile_data_area() {
+            0x8000 + usize::from(tile_number) * 16
+        } else {
+            (0x9000 + i32::from(tile_number as i8) * 16) as usize
         }
+    }
 
-        tile
+    /// a tile row is 2 bytes, the first holds the low bit of every pixel and the second the high bit
+    fn get_tile_pixel(&self, tile_location: usize, x: usize, y: usize) -> u8 {
+        let low = self.get_byte_from_location(tile_location + y * 2);
+        let high = self.get_byte_from_location(tile_location + y * 2 + 1);
+        let bit = 7 - x;
+        (((high >> bit) & 1) << 1) | ((low >> bit) & 1)
+    }
+
+    fn get_lcd_enable(&self) -> bool {
+        let num = self.lcdc & 0x80;
+        num == 0x80
     }
 
     /// get background tile map area FALSE is area 0 TRUE is area 1
@@ -275,6 +489,12 @@ impl Renderer {
         num == 0x8
     }
 
+    /// the window is only drawn when the background is enabled as well
+    fn get_window_enable(&self) -> bool {
+        let num = self.lcdc & 0x20;
+        num == 0x20
+    }
+
     fn get_window_tile_map_area(&self) -> bool {
         let num = self.lcdc & 0x40;
         num == 0x40
@@ -312,25 +532,246 @@ impl Renderer {
             8
         }
     }
+}
 
-    fn convert_tile(&self, tile_data: [u8; 16]) -> Tile {
-        let mut colors: [[Color; 8]; 8] = [[Color::WHITE; 8]; 8];
-        let mut i: usize = 0;
-        for byte in tile_data {
-            for number in 0..4 {
-                let color = self.get_color_from_byte(byte, &number);
-                let x = i % 8;
-                let y = i / 8;
-                colors[y][x] = color;
-                i += 1;
-            }
-        }
 
-        Tile { pixels: colors }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ly_and_vblank() -> Result<(), String> {
+        let mut renderer = Renderer::new();
+        assert_eq!(renderer.mode, Mode::OamSearch);
+        renderer.tick(OAM_SEARCH_DOTS);
+        assert_eq!(renderer.mode, Mode::PixelTransfer);
+        renderer.tick(PIXEL_TRANSFER_DOTS);
+        assert_eq!(renderer.mode, Mode::HBlank);
+        renderer.tick(LINE_DOTS - OAM_SEARCH_DOTS - PIXEL_TRANSFER_DOTS);
+        assert_eq!(renderer.get_ly(), 1);
+        assert_eq!(renderer.mode, Mode::OamSearch);
+
+        assert_eq!(renderer.tick(LINE_DOTS * 142), (false, false));
+        assert_eq!(renderer.get_ly(), 143);
+        assert_eq!(renderer.tick(LINE_DOTS), (true, false));
+        assert_eq!(renderer.mode, Mode::VBlank);
+        assert_eq!(renderer.get_stat() & 0x03, 1);
+        renderer.tick(LINE_DOTS * 10);
+        assert_eq!(renderer.get_ly(), 0);
+        assert_eq!(renderer.mode, Mode::OamSearch);
+        Ok(())
+    }
+
+    #[test]
+    fn test_stat_interrupt() -> Result<(), String> {
+        let mut renderer = Renderer::new();
+        renderer.set_lyc(2);
+        renderer.set_stat(0x40);
+        assert_eq!(renderer.get_stat(), 0xc2);
+        assert_eq!(renderer.tick(LINE_DOTS), (false, false));
+        assert_eq!(renderer.tick(LINE_DOTS), (false, true));
+        assert_eq!(renderer.get_stat() & 0x04, 0x04);
+
+        renderer.set_stat(0x08);
+        assert_eq!(renderer.tick(OAM_SEARCH_DOTS + PIXEL_TRANSFER_DOTS), (false, true));
+        assert_eq!(renderer.tick(10), (false, false));
+        Ok(())
+    }
+
+    #[test]
+    fn test_lcd_off() -> Result<(), String> {
+        let mut renderer = Renderer::new();
+        renderer.tick(LINE_DOTS * 3);
+        renderer.set_lcdc(0x11);
+        assert_eq!(renderer.get_ly(), 0);
+        assert_eq!(renderer.tick(LINE_DOTS * 200), (false, false));
+        assert_eq!(renderer.get_ly(), 0);
+        renderer.set_lcdc(0x91);
+        renderer.tick(LINE_DOTS);
+        assert_eq!(renderer.get_ly(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_line() -> Result<(), String> {
+        let mut renderer = Renderer::new();
+        // tile 1 row 0 has the color numbers 3 2 1 0 3 2 1 0
+        renderer.store(0x8010, 0b1010_1010);
+        renderer.store(0x8011, 0b1100_1100);
+        renderer.store(0x9801, 0x01);
+        renderer.set_bgp(0xe4);
+        renderer.tick(OAM_SEARCH_DOTS + PIXEL_TRANSFER_DOTS);
+        let screen = renderer.get_screen();
+        assert_eq!(screen[7].color, COLORS[0]);
+        assert_eq!(screen[8].color, COLORS[3]);
+        assert_eq!(screen[9].color, COLORS[2]);
+        assert_eq!(screen[10].color, COLORS[1]);
+        assert_eq!(screen[11].color, COLORS[0]);
+
+        // in area 0 tile 0 is at 0x9000
+        renderer.store(0x9000, 0xff);
+        renderer.set_lcdc(0x81);
+        renderer.tick(LINE_DOTS * 154);
+        assert_eq!(renderer.get_screen()[0].color, COLORS[1]);
+        Ok(())
+    }
+
+    /// tile 1 is filled with color 3, tile 2 with color 1
+    fn get_tile_renderer() -> Renderer {
+        let mut renderer = Renderer::new();
+        renderer.set_bgp(0xe4);
+        for i in 0..16 {
+            renderer.store(0x8010 + i, 0xff);
+            renderer.store(0x8020 + i, if i % 2 == 0 { 0xff } else { 0x00 });
+        }
+        renderer
     }
 
-    fn get_color_from_byte(&self, byte: u8, number: &u8) -> Color {
-        let get_color = (byte >> number * 2) & 3;
-        self.color[usize::from(get_color)]
+    fn get_color_number(renderer: &Renderer, x: i32, y: i32) -> usize {
+        let color = renderer.get_screen()[(y * WIDTH + x) as usize].color;
+        COLORS.iter().position(|c| *c == color).unwrap()
+    }
+
+    #[test]
+    fn test_scroll() -> Result<(), String> {
+        let mut renderer = get_tile_renderer();
+        // the last tile of the first row and the first tile of the second row
+        renderer.store(0x981f, 0x01);
+        renderer.store(0x9820, 0x02);
+        renderer.set_scx(0xfc);
+        renderer.set_scy(0x06);
+        renderer.tick(LINE_DOTS * 3);
+        assert_eq!(get_color_number(&renderer, 3, 0), 3);
+        assert_eq!(get_color_number(&renderer, 4, 0), 0);
+        assert_eq!(get_color_number(&renderer, 3, 1), 3);
+        assert_eq!(get_color_number(&renderer, 3, 2), 0);
+        assert_eq!(get_color_number(&renderer, 4, 2), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_window() -> Result<(), String> {
+        let mut renderer = get_tile_renderer();
+        renderer.store(0x9c00, 0x01);
+        renderer.store(0x9c20, 0x02);
+        renderer.set_lcdc(0xf1);
+        renderer.set_wy(2);
+        renderer.set_wx(17);
+        renderer.tick(LINE_DOTS * 11);
+        assert_eq!(get_color_number(&renderer, 10, 1), 0);
+        assert_eq!(get_color_number(&renderer, 9, 2), 0);
+        assert_eq!(get_color_number(&renderer, 10, 2), 3);
+        assert_eq!(get_color_number(&renderer, 17, 9), 3);
+        assert_eq!(get_color_number(&renderer, 18, 9), 0);
+        assert_eq!(get_color_number(&renderer, 10, 10), 1);
+
+        // the window line counter doesn't advance while the window is hidden
+        renderer.tick(LINE_DOTS * 143);
+        renderer.tick(LINE_DOTS * 4);
+        renderer.set_wx(200);
+        renderer.tick(LINE_DOTS * 4);
+        renderer.set_wx(7);
+        renderer.tick(LINE_DOTS * 7);
+        assert_eq!(get_color_number(&renderer, 0, 8), 3);
+        assert_eq!(get_color_number(&renderer, 0, 13), 3);
+        assert_eq!(get_color_number(&renderer, 0, 14), 1);
+
+        renderer.set_lcdc(0xd1);
+        renderer.tick(LINE_DOTS * 154);
+        assert_eq!(get_color_number(&renderer, 0, 8), 0);
+        Ok(())
+    }
+
+    fn set_sprite(renderer: &mut Renderer, number: usize, y: u8, x: u8, tile: u8, flags: u8) {
+        renderer.oam_data[number * 4..number * 4 + 4].copy_from_slice(&[y, x, tile, flags]);
+    }
+
+    #[test]
+    fn test_sprites() -> Result<(), String> {
+        let mut renderer = get_tile_renderer();
+        renderer.set_obp0(0xe4);
+        renderer.set_obp1(0x1b);
+        // tile 3 has color 2 in its first column and color 0 everywhere else
+        for i in 0..8 {
+            renderer.store(0x8030 + i * 2 + 1, 0x80);
+        }
+        renderer.set_lcdc(0x93);
+        set_sprite(&mut renderer, 0, 16, 9, 0x01, 0x00);
+        set_sprite(&mut renderer, 1, 16, 8, 0x03, 0x00);
+        set_sprite(&mut renderer, 2, 20, 20, 0x02, 0x10);
+        renderer.tick(LINE_DOTS * 8);
+        // the sprite with the lower X is on top but its transparent pixels show the sprite below
+        assert_eq!(get_color_number(&renderer, 0, 0), 2);
+        assert_eq!(get_color_number(&renderer, 1, 0), 3);
+        assert_eq!(get_color_number(&renderer, 8, 0), 3);
+        assert_eq!(get_color_number(&renderer, 9, 0), 0);
+        // OBP1 maps color 1 to shade 2
+        assert_eq!(get_color_number(&renderer, 12, 3), 0);
+        assert_eq!(get_color_number(&renderer, 12, 4), 2);
+
+        renderer.set_lcdc(0x91);
+        renderer.tick(LINE_DOTS * 154);
+        assert_eq!(get_color_number(&renderer, 1, 0), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sprite_flip_and_size() -> Result<(), String> {
+        let mut renderer = get_tile_renderer();
+        renderer.set_obp0(0xe4);
+        // tile 6 has color 1 in the top left corner and tile 7 in the bottom right corner
+        renderer.store(0x8060, 0x80);
+        renderer.store(0x807e, 0x01);
+        renderer.set_lcdc(0x97);
+        // 0x07 is masked to 6, flipping both ways puts the corner of tile 7 at the top left
+        set_sprite(&mut renderer, 0, 16, 8, 0x07, 0x60);
+        renderer.tick(LINE_DOTS * 16);
+        assert_eq!(get_color_number(&renderer, 0, 0), 1);
+        assert_eq!(get_color_number(&renderer, 7, 0), 0);
+        assert_eq!(get_color_number(&renderer, 0, 15), 0);
+        assert_eq!(get_color_number(&renderer, 7, 15), 1);
+
+        set_sprite(&mut renderer, 0, 16, 8, 0x06, 0x20);
+        renderer.tick(LINE_DOTS * 154);
+        assert_eq!(get_color_number(&renderer, 7, 0), 1);
+        assert_eq!(get_color_number(&renderer, 0, 15), 1);
+        assert_eq!(get_color_number(&renderer, 7, 15), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sprite_limit_and_priority() -> Result<(), String> {
+        let mut renderer = get_tile_renderer();
+        renderer.set_obp0(0xe4);
+        renderer.store(0x9800, 0x02);
+        renderer.set_lcdc(0x93);
+        for number in 0..11 {
+            set_sprite(&mut renderer, number, 16, 8 + number as u8 * 8, 0x01, 0x00);
+        }
+        set_sprite(&mut renderer, 0, 16, 8, 0x01, 0x80);
+        renderer.tick(LINE_DOTS);
+        // the background covers the sprite where its color isn't 0
+        assert_eq!(get_color_number(&renderer, 0, 0), 1);
+        assert_eq!(get_color_number(&renderer, 72, 0), 3);
+        assert_eq!(get_color_number(&renderer, 80, 0), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_background_palette() -> Result<(), String> {
+        let mut renderer = get_tile_renderer();
+        renderer.store(0x9800, 0x01);
+        renderer.store(0x9801, 0x02);
+        renderer.set_bgp(0b00_01_11_10);
+        renderer.tick(LINE_DOTS);
+        assert_eq!(get_color_number(&renderer, 0, 0), 0);
+        assert_eq!(get_color_number(&renderer, 8, 0), 3);
+        assert_eq!(get_color_number(&renderer, 16, 0), 2);
+
+        let colors = [Color::RGB(1, 2, 3), Color::RGB(4, 5, 6), Color::RGB(7, 8, 9), Color::RGB(10, 11, 12)];
+        renderer.set_colors(colors);
+        assert_eq!(renderer.get_screen()[0].color, Color::RGB(1, 2, 3));
+        assert_eq!(renderer.get_screen()[8].color, Color::RGB(10, 11, 12));
+        Ok(())
     }
 }
@@ -0,0 +1,180 @@
+use std::collections::VecDeque;
+
+/// an older state stored as the difference to the state taken after it
+struct Snapshot {
+    length: usize,
+    delta: Vec<u8>,
+}
+
+/// a ring buffer of save states, only the newest state is kept whole,
+/// every older state is the xor with its successor with the runs of zeros compressed
+pub struct Rewind {
+    snapshots: VecDeque<Snapshot>,
+    current: Option<Vec<u8>>,
+    memory_limit: usize,
+    used_memory: usize,
+}
+
+impl Rewind {
+    /// memory_limit is the amount of bytes the snapshots may use together
+    pub fn new(memory_limit: usize) -> Self {
+        Rewind {
+            snapshots: VecDeque::new(),
+            current: None,
+            memory_limit,
+            used_memory: 0,
+        }
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        if let Some(previous) = self.current.take() {
+            let delta = compress(&xor(&previous, &state));
+            self.used_memory += delta.len();
+            self.snapshots.push_back(Snapshot { length: previous.len(), delta });
+            self.used_memory -= previous.len();
+        }
+        self.used_memory += state.len();
+        self.current = Some(state);
+        while self.used_memory > self.memory_limit {
+            match self.snapshots.pop_front() {
+                Some(snapshot) => self.used_memory -= snapshot.delta.len(),
+                None => {
+                    self.used_memory = 0;
+                    self.current = None;
+                },
+            }
+        }
+    }
+
+    /// take the newest state out of the buffer, the state before it becomes the newest
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let state = self.current.take()?;
+        self.used_memory -= state.len();
+        if let Some(snapshot) = self.snapshots.pop_back() {
+            self.used_memory -= snapshot.delta.len();
+            let mut previous = xor(&decompress(&snapshot.delta), &state);
+            previous.truncate(snapshot.length);
+            self.used_memory += previous.len();
+            self.current = Some(previous);
+        }
+        Some(state)
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.current = None;
+        self.used_memory = 0;
+    }
+}
+
+/// states can differ in length, the shorter one is padded with zeros
+fn xor(first: &[u8], second: &[u8]) -> Vec<u8> {
+    let length = first.len().max(second.len());
+    (0..length)
+        .map(|index| first.get(index).unwrap_or(&0) ^ second.get(index).unwrap_or(&0))
+        .collect()
+}
+
+/// the delta of two states is mostly zero, it is stored as
+/// pairs of a zero run and a literal run followed by the literal bytes
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut index = 0;
+    while index < data.len() {
+        let zeros = data[index..].iter().take_while(|byte| **byte == 0).count();
+        index += zeros;
+        let literals = data[index..].iter().take_while(|byte| **byte != 0).count();
+        write_length(&mut output, zeros);
+        write_length(&mut output, literals);
+        output.extend_from_slice(&data[index..index + literals]);
+        index += literals;
+    }
+    output
+}
+
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut index = 0;
+    while index < data.len() {
+        let zeros = read_length(data, &mut index);
+        let literals = read_length(data, &mut index);
+        output.resize(output.len() + zeros, 0);
+        output.extend_from_slice(&data[index..index + literals]);
+        index += literals;
+    }
+    output
+}
+
+/// 7 bits per byte, the high bit is set when another byte follows
+fn write_length(output: &mut Vec<u8>, mut length: usize) {
+    while length >= 0x80 {
+        output.push((length as u8 & 0x7f) | 0x80);
+        length >>= 7;
+    }
+    output.push(length as u8);
+}
+
+fn read_length(data: &[u8], index: &mut usize) -> usize {
+    let mut length = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*index];
+        *index += 1;
+        length |= usize::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return length;
+        }
+        shift += 7;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compress() -> Result<(), String> {
+        let mut data = vec![0; 1000];
+        data[3] = 1;
+        data[4] = 2;
+        data[999] = 3;
+        let compressed = compress(&data);
+        assert!(compressed.len() < 12);
+        assert_eq!(decompress(&compressed), data);
+        assert_eq!(decompress(&compress(&[5, 0, 6])), vec![5, 0, 6]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_push_pop() -> Result<(), String> {
+        let mut rewind = Rewind::new(1000);
+        rewind.push(vec![1, 2, 3]);
+        rewind.push(vec![1, 2, 4, 5]);
+        rewind.push(vec![7, 2]);
+        assert_eq!(rewind.pop(), Some(vec![7, 2]));
+        assert_eq!(rewind.pop(), Some(vec![1, 2, 4, 5]));
+        rewind.push(vec![9]);
+        assert_eq!(rewind.pop(), Some(vec![9]));
+        assert_eq!(rewind.pop(), Some(vec![1, 2, 3]));
+        assert_eq!(rewind.pop(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory_limit() -> Result<(), String> {
+        let mut rewind = Rewind::new(250);
+        for value in 1..=10 {
+            rewind.push(vec![value; 100]);
+        }
+        assert!(rewind.used_memory <= 250);
+        assert_eq!(rewind.pop(), Some(vec![10; 100]));
+        assert_eq!(rewind.pop(), Some(vec![9; 100]));
+        assert_eq!(rewind.pop(), None);
+
+        let mut rewind = Rewind::new(10);
+        rewind.push(vec![1; 100]);
+        assert_eq!(rewind.pop(), None);
+        Ok(())
+    }
+}
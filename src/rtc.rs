@@ -0,0 +1,264 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+/// the size of the rtc block saved after the battery ram, the same layout as other emulators use
+pub const RTC_SAVE_SIZE: usize = 48;
+
+/// a source of wall clock time in seconds
+pub trait Clock {
+    fn now(&self) -> u64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+/// a clock that never moves, so runs that must repeat exactly don't depend on the time of day
+pub struct FixedClock(pub u64);
+
+impl Clock for FixedClock {
+    fn now(&self) -> u64 {
+        self.0
+    }
+}
+
+/// the real time clock of an MBC3 cardridge
+#[derive(Serialize, Deserialize)]
+pub struct Rtc {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days: u16,
+    halted: bool,
+    day_carry: bool,
+    latched: [u8; 5],
+    /// the clock time the registers were last brought up to date
+    last_update: u64,
+    #[serde(skip, default = "get_system_clock")]
+    clock: Box<dyn Clock>,
+}
+
+fn get_system_clock() -> Box<dyn Clock> {
+    Box::new(SystemClock)
+}
+
+impl Rtc {
+    pub fn new(clock: Box<dyn Clock>) -> Self {
+        let last_update = clock.now();
+        Rtc {
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            days: 0,
+            halted: false,
+            day_carry: false,
+            latched: [0; 5],
+            last_update,
+            clock,
+        }
+    }
+
+    /// bring the registers up to date with the old clock before switching to the new one
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.update();
+        self.last_update = clock.now();
+        self.clock = clock;
+    }
+
+    /// copy the running registers into the latched registers the game can read
+    pub fn latch(&mut self) {
+        self.update();
+        self.latched = self.get_registers();
+    }
+
+    /// read a latched register, 0x08 seconds, 0x09 minutes, 0x0a hours, 0x0b day low, 0x0c day high
+    pub fn read(&self, register: u8) -> u8 {
+        match register {
+            0x08..=0x0c => self.latched[usize::from(register - 0x08)],
+            _ => 0xff,
+        }
+    }
+
+    /// returns true if the write changed a register
+    pub fn write(&mut self, register: u8, value: u8) -> bool {
+        self.update();
+        let registers = self.get_registers();
+        match register {
+            0x08 => self.seconds = value & 0x3f,
+            0x09 => self.minutes = value & 0x3f,
+            0x0a => self.hours = value & 0x1f,
+            0x0b => self.days = (self.days & 0x100) | u16::from(value),
+            0x0c => {
+                self.days = (self.days & 0xff) | (u16::from(value & 0x01) << 8);
+                self.halted = value & 0x40 == 0x40;
+                self.day_carry = value & 0x80 == 0x80;
+            },
+            _ => (),
+        }
+        self.get_registers() != registers
+    }
+
+    /// the registers followed by the latched registers as 32 bit values and the time of the last update
+    pub fn save(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(RTC_SAVE_SIZE);
+        for register in self.get_registers().iter().chain(self.latched.iter()) {
+            data.extend_from_slice(&u32::from(*register).to_le_bytes());
+        }
+        data.extend_from_slice(&self.last_update.to_le_bytes());
+        data
+    }
+
+    /// restore the rtc from save data, the time that passed since the save is added on the next update
+    pub fn load(&mut self, data: &[u8]) {
+        if data.len() < RTC_SAVE_SIZE {
+            return;
+        }
+        let values: Vec<u8> = data[..40].chunks(4).map(|chunk| chunk[0]).collect();
+        self.seconds = values[0] & 0x3f;
+        self.minutes = values[1] & 0x3f;
+        self.hours = values[2] & 0x1f;
+        self.days = u16::from(values[3]) | (u16::from(values[4] & 0x01) << 8);
+        self.halted = values[4] & 0x40 == 0x40;
+        self.day_carry = values[4] & 0x80 == 0x80;
+        self.latched.copy_from_slice(&values[5..10]);
+        let mut timestamp = [0; 8];
+        timestamp.copy_from_slice(&data[40..48]);
+        self.last_update = u64::from_le_bytes(timestamp);
+    }
+
+    fn get_registers(&self) -> [u8; 5] {
+        let [day_high, day_low] = self.days.to_be_bytes();
+        let halted = if self.halted { 0x40 } else { 0 };
+        let day_carry = if self.day_carry { 0x80 } else { 0 };
+        [self.seconds, self.minutes, self.hours, day_low, day_high | halted | day_carry]
+    }
+
+    /// add the time that passed since the last update unless the clock is halted
+    fn update(&mut self) {
+        let now = self.clock.now();
+        let elapsed = now.saturating_sub(self.last_update);
+        self.last_update = now;
+        if self.halted || elapsed == 0 {
+            return;
+        }
+        let mut total = u64::from(self.seconds)
+            + u64::from(self.minutes) * 60
+            + u64::from(self.hours) * 3600
+            + u64::from(self.days) * 86400
+            + elapsed;
+        self.seconds = (total % 60) as u8;
+        total /= 60;
+        self.minutes = (total % 60) as u8;
+        total /= 60;
+        self.hours = (total % 24) as u8;
+        total /= 24;
+        if total > 0x1ff {
+            self.day_carry = true;
+        }
+        self.days = (total % 0x200) as u16;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    struct TestClock {
+        time: Rc<Cell<u64>>,
+    }
+
+    impl Clock for TestClock {
+        fn now(&self) -> u64 {
+            self.time.get()
+        }
+    }
+
+    fn get_rtc(start: u64) -> (Rtc, Rc<Cell<u64>>) {
+        let time = Rc::new(Cell::new(start));
+        let rtc = Rtc::new(Box::new(TestClock { time: time.clone() }));
+        (rtc, time)
+    }
+
+    #[test]
+    fn test_set_clock() -> Result<(), String> {
+        let (mut rtc, time) = get_rtc(1000);
+        time.set(1010);
+        rtc.set_clock(Box::new(FixedClock(5)));
+        time.set(5000);
+        rtc.latch();
+        assert_eq!(rtc.read(0x08), 10);
+        assert_eq!(rtc.read(0x09), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rtc_counts() -> Result<(), String> {
+        let (mut rtc, time) = get_rtc(1000);
+        time.set(1000 + 86400 + 3600 * 2 + 60 * 3 + 4);
+        assert_eq!(rtc.read(0x08), 0);
+        rtc.latch();
+        assert_eq!(rtc.read(0x08), 4);
+        assert_eq!(rtc.read(0x09), 3);
+        assert_eq!(rtc.read(0x0a), 2);
+        assert_eq!(rtc.read(0x0b), 1);
+        assert_eq!(rtc.read(0x0c), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rtc_halt() -> Result<(), String> {
+        let (mut rtc, time) = get_rtc(0);
+        assert!(rtc.write(0x0c, 0x40));
+        assert!(!rtc.write(0x0c, 0x40));
+        time.set(100);
+        rtc.latch();
+        assert_eq!(rtc.read(0x08), 0);
+        rtc.write(0x08, 10);
+        rtc.write(0x0c, 0x00);
+        time.set(105);
+        rtc.latch();
+        assert_eq!(rtc.read(0x08), 15);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rtc_day_carry() -> Result<(), String> {
+        let (mut rtc, time) = get_rtc(0);
+        rtc.write(0x0b, 0xff);
+        rtc.write(0x0c, 0x01);
+        time.set(86400);
+        rtc.latch();
+        assert_eq!(rtc.read(0x0b), 0);
+        assert_eq!(rtc.read(0x0c), 0x80);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rtc_save_load() -> Result<(), String> {
+        let (mut rtc, time) = get_rtc(500);
+        rtc.write(0x09, 30);
+        rtc.latch();
+        let data = rtc.save();
+        assert_eq!(data.len(), RTC_SAVE_SIZE);
+
+        time.set(500 + 3600);
+        let mut loaded = Rtc::new(Box::new(TestClock { time: time.clone() }));
+        loaded.load(&data);
+        assert_eq!(loaded.read(0x09), 30);
+        loaded.latch();
+        assert_eq!(loaded.read(0x09), 30);
+        assert_eq!(loaded.read(0x0a), 1);
+        Ok(())
+    }
+}
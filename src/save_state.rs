@@ -0,0 +1,169 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::cpu::Cpu;
+
+const MAGIC: [u8; 4] = *b"RBSS";
+/// increase when the layout of a saved struct changes, older states can't be loaded after that
+pub const VERSION: u32 = 9;
+
+/// written in front of the machine state
+#[derive(Serialize, Deserialize)]
+struct StateHeader {
+    magic: [u8; 4],
+    version: u32,
+    rom_checksum: u64,
+}
+
+#[derive(Debug)]
+pub enum SaveStateError {
+    Io(io::Error),
+    /// the file doesn't start with the save state magic
+    NotASaveState,
+    UnsupportedVersion { expected: u32, actual: u32 },
+    /// the state was saved while running a different rom
+    RomMismatch,
+    Corrupt(String),
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveStateError::Io(error) => write!(f, "{}", error),
+            SaveStateError::NotASaveState => write!(f, "not a save state"),
+            SaveStateError::UnsupportedVersion { expected, actual } =>
+                write!(f, "save state version {} is not supported, expected version {}", actual, expected),
+            SaveStateError::RomMismatch => write!(f, "save state belongs to a different rom"),
+            SaveStateError::Corrupt(error) => write!(f, "save state is corrupt: {}", error),
+        }
+    }
+}
+
+impl From<io::Error> for SaveStateError {
+    fn from(error: io::Error) -> Self {
+        SaveStateError::Io(error)
+    }
+}
+
+/// the state file for a slot is stored next to the rom, slot 1 of game.gb is game.ss1
+pub fn get_slot_path(rom_path: &Path, slot: u8) -> PathBuf {
+    rom_path.with_extension(format!("ss{}", slot))
+}
+
+/// serialize the whole machine, the rom itself is only stored as a checksum
+pub fn save(cpu: &Cpu) -> Vec<u8> {
+    let header = StateHeader {
+        magic: MAGIC,
+        version: VERSION,
+        rom_checksum: get_rom_checksum(&cpu.memory_map.cardridge.memory),
+    };
+    let mut data = bincode::serialize(&header).expect("could not serialize save state header");
+    bincode::serialize_into(&mut data, cpu).expect("could not serialize save state");
+    data
+}
+
+/// replace the machine with a saved state, the cpu is left untouched if the state can't be loaded
+pub fn load(cpu: &mut Cpu, data: &[u8]) -> Result<(), SaveStateError> {
+    let mut reader = data;
+    let header: StateHeader = bincode::deserialize_from(&mut reader)
+        .map_err(|_| SaveStateError::NotASaveState)?;
+    if header.magic != MAGIC {
+        return Err(SaveStateError::NotASaveState);
+    }
+    if header.version != VERSION {
+        return Err(SaveStateError::UnsupportedVersion { expected: VERSION, actual: header.version });
+    }
+    if header.rom_checksum != get_rom_checksum(&cpu.memory_map.cardridge.memory) {
+        return Err(SaveStateError::RomMismatch);
+    }
+    let mut state: Cpu = bincode::deserialize_from(&mut reader)
+        .map_err(|error| SaveStateError::Corrupt(error.to_string()))?;
+    state.memory_map.cardridge.memory = mem::take(&mut cpu.memory_map.cardridge.memory);
+    state.memory_map.renderer.set_colors(cpu.memory_map.renderer.get_colors());
+    state.memory_map.apu.keep_output(&mut cpu.memory_map.apu);
+    *cpu = state;
+    Ok(())
+}
+
+pub fn save_to_file(cpu: &Cpu, path: &Path) -> Result<(), SaveStateError> {
+    fs::write(path, save(cpu))?;
+    Ok(())
+}
+
+pub fn load_from_file(cpu: &mut Cpu, path: &Path) -> Result<(), SaveStateError> {
+    let data = fs::read(path)?;
+    load(cpu, &data)
+}
+
+/// 64 bit FNV-1a over the whole rom
+fn get_rom_checksum(rom: &[u8]) -> u64 {
+    rom.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cardridge::Cardridge;
+
+    fn get_cpu(rom: Vec<u8>) -> Cpu {
+        Cpu::new(Cardridge::rom_only(rom))
+    }
+
+    #[test]
+    fn test_save_load() -> Result<(), String> {
+        let mut cpu = get_cpu(vec![0x3c; 0x8000]);
+        cpu.start_cycle();
+        cpu.memory_map.store_8bit_full_address(0xc000, 0x42);
+        let data = save(&cpu);
+
+        let mut loaded = get_cpu(vec![0x3c; 0x8000]);
+        load(&mut loaded, &data).map_err(|e| e.to_string())?;
+        assert_eq!(loaded.memory_map.get_8bit_full_address(0xc000), 0x42);
+        assert_eq!(loaded.memory_map.get_8bit_full_address(0x0000), 0x3c);
+        assert_eq!(save(&loaded), data);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rom_mismatch() -> Result<(), String> {
+        let data = save(&get_cpu(vec![0x3c; 0x8000]));
+        let mut cpu = get_cpu(vec![0x00; 0x8000]);
+        cpu.memory_map.store_8bit_full_address(0xc000, 0x42);
+        match load(&mut cpu, &data) {
+            Err(SaveStateError::RomMismatch) => (),
+            _ => return Err("expected a rom mismatch".to_string()),
+        }
+        assert_eq!(cpu.memory_map.get_8bit_full_address(0xc000), 0x42);
+        Ok(())
+    }
+
+    #[test]
+    fn test_version() -> Result<(), String> {
+        let mut cpu = get_cpu(vec![0x00; 0x8000]);
+        let mut data = save(&cpu);
+        data[4..8].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        match load(&mut cpu, &data) {
+            Err(SaveStateError::UnsupportedVersion { expected: VERSION, actual }) if actual == VERSION + 1 => (),
+            _ => return Err("expected an unsupported version".to_string()),
+        }
+        match load(&mut cpu, b"not a state") {
+            Err(SaveStateError::NotASaveState) => (),
+            _ => return Err("expected not a save state".to_string()),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_slot_path() -> Result<(), String> {
+        assert_eq!(get_slot_path(Path::new("roms/game.gb"), 3), PathBuf::from("roms/game.ss3"));
+        Ok(())
+    }
+}
@@ -4,20 +4,78 @@ use std::{fs::{self, File}, io::Write};
 const PATH: &str = "settings.json";
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Settings {
-    pub render_scale: u32
+    pub render_scale: u32,
+    /// the memory the rewind buffer may use in MiB, 0 turns rewinding off
+    #[serde(default = "get_default_rewind_size")]
+    pub rewind_size: usize,
+    /// a rewind snapshot is taken every this many frames
+    #[serde(default = "get_default_rewind_interval")]
+    pub rewind_interval: usize,
+    #[serde(default)]
+    pub color_scheme: ColorScheme,
+    /// the sample rate the audio device is opened with
+    #[serde(default = "get_default_sample_rate")]
+    pub sample_rate: u32,
+    /// pace the emulation by the audio device instead of a timer when there is sound
+    #[serde(default = "get_default_audio_sync")]
+    pub audio_sync: bool,
+}
+
+/// the colors of the four shades from lightest to darkest
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub enum ColorScheme {
+    ClassicGreen,
+    #[default]
+    PocketGray,
+    Custom([[u8; 3]; 4]),
+}
+
+impl ColorScheme {
+    pub fn get_colors(&self) -> [[u8; 3]; 4] {
+        match self {
+            ColorScheme::ClassicGreen => [[0x9b, 0xbc, 0x0f], [0x8b, 0xac, 0x0f], [0x30, 0x62, 0x30], [0x0f, 0x38, 0x0f]],
+            ColorScheme::PocketGray => [[0xff, 0xff, 0xff], [0xa9, 0xa9, 0xa9], [0x69, 0x69, 0x69], [0x00, 0x00, 0x00]],
+            ColorScheme::Custom(colors) => *colors,
+        }
+    }
+}
+
+fn get_default_rewind_size() -> usize {
+    32
+}
+
+fn get_default_rewind_interval() -> usize {
+    5
+}
+
+fn get_default_sample_rate() -> u32 {
+    48_000
+}
+
+fn get_default_audio_sync() -> bool {
+    true
 }
 
 impl Settings {
     pub fn get_settings() -> Self {
         let file = File::open(PATH);
-        let settings = match file {
+        let mut settings = match file {
             Ok(_) => Self::serialize(PATH),
             Err(_) => Self::default(),
     };
 
+    settings.validate();
     settings
     }
 
+    /// replace values that can't be used with their default
+    fn validate(&mut self) {
+        if self.sample_rate == 0 {
+            println!("sample_rate can't be 0, using {}", get_default_sample_rate());
+            self.sample_rate = get_default_sample_rate();
+        }
+    }
+
     pub fn deserialize(&self) {
         let result = fs::File::create(PATH);
         match result {
@@ -36,7 +94,12 @@ impl Settings {
 
     fn default() -> Self {
         let default = Settings {
-            render_scale: 1
+            render_scale: 1,
+            rewind_size: get_default_rewind_size(),
+            rewind_interval: get_default_rewind_interval(),
+            color_scheme: ColorScheme::default(),
+            sample_rate: get_default_sample_rate(),
+            audio_sync: get_default_audio_sync(),
         };
 
         default.deserialize();
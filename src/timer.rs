@@ -0,0 +1,184 @@
+use serde::{Deserialize, Serialize};
+
+/// the T-cycles between TIMA overflowing and being reloaded from TMA
+const RELOAD_DELAY: u8 = 4;
+
+#[derive(Serialize, Deserialize)]
+pub struct Timer {
+    system_counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    reload_delay: u8,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Timer {
+            system_counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            reload_delay: 0,
+        }
+    }
+
+    /// advance the timer by the given amount of T-cycles,
+    /// returns true when the timer interrupt should be requested
+    pub fn tick(&mut self, cycles: usize) -> bool {
+        let mut interrupt = false;
+        for _ in 0..cycles {
+            if self.reload_delay > 0 {
+                self.reload_delay -= 1;
+                if self.reload_delay == 0 {
+                    self.tima = self.tma;
+                    interrupt = true;
+                }
+            }
+            let old_signal = self.get_signal();
+            self.system_counter = self.system_counter.wrapping_add(1);
+            self.check_falling_edge(old_signal);
+        }
+        interrupt
+    }
+
+    /// DIV is the upper byte of the system counter
+    pub fn get_div(&self) -> u8 {
+        self.system_counter.to_be_bytes()[0]
+    }
+
+    pub fn get_tima(&self) -> u8 {
+        self.tima
+    }
+
+    pub fn get_tma(&self) -> u8 {
+        self.tma
+    }
+
+    /// the upper 5 bits of TAC are unused and read as 1
+    pub fn get_tac(&self) -> u8 {
+        self.tac | 0xf8
+    }
+
+    /// any write to DIV resets the whole system counter
+    pub fn reset_div(&mut self) {
+        let old_signal = self.get_signal();
+        self.system_counter = 0;
+        self.check_falling_edge(old_signal);
+    }
+
+    /// writing TIMA while the reload is pending cancels the reload
+    pub fn set_tima(&mut self, value: u8) {
+        self.tima = value;
+        self.reload_delay = 0;
+    }
+
+    pub fn set_tma(&mut self, value: u8) {
+        self.tma = value;
+    }
+
+    pub fn set_tac(&mut self, value: u8) {
+        let old_signal = self.get_signal();
+        self.tac = value & 0x07;
+        self.check_falling_edge(old_signal);
+    }
+
+    /// TIMA is incremented when the selected system counter bit AND the enable bit falls from 1 to 0
+    fn check_falling_edge(&mut self, old_signal: bool) {
+        if old_signal && !self.get_signal() {
+            self.increment_tima();
+        }
+    }
+
+    fn increment_tima(&mut self) {
+        let overflow = self.tima.overflowing_add(1);
+        self.tima = overflow.0;
+        if overflow.1 {
+            self.reload_delay = RELOAD_DELAY;
+        }
+    }
+
+    fn get_signal(&self) -> bool {
+        let enabled = self.tac & 0x04 == 0x04;
+        enabled && self.system_counter & self.get_frequency_bit() != 0
+    }
+
+    /// the system counter bit selected by TAC, 4096, 262144, 65536 or 16384 Hz
+    fn get_frequency_bit(&self) -> u16 {
+        match self.tac & 0x03 {
+            0b00 => 1 << 9,
+            0b01 => 1 << 3,
+            0b10 => 1 << 5,
+            _    => 1 << 7,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_div() -> Result<(), String> {
+        let mut timer = Timer::new();
+        timer.tick(255);
+        assert_eq!(timer.get_div(), 0);
+        timer.tick(1);
+        assert_eq!(timer.get_div(), 1);
+        timer.reset_div();
+        assert_eq!(timer.get_div(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tima_frequency() -> Result<(), String> {
+        let mut timer = Timer::new();
+        timer.tick(16);
+        assert_eq!(timer.get_tima(), 0);
+        timer.set_tac(0x05);
+        timer.tick(16);
+        assert_eq!(timer.get_tima(), 1);
+        timer.set_tac(0x04);
+        timer.tick(1024);
+        assert_eq!(timer.get_tima(), 2);
+        assert_eq!(timer.get_tac(), 0xfc);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tima_overflow() -> Result<(), String> {
+        let mut timer = Timer::new();
+        timer.set_tac(0x05);
+        timer.set_tma(0xf0);
+        timer.set_tima(0xff);
+        assert!(!timer.tick(16));
+        assert_eq!(timer.get_tima(), 0);
+        assert!(!timer.tick(3));
+        assert!(timer.tick(1));
+        assert_eq!(timer.get_tima(), 0xf0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tima_write_cancels_reload() -> Result<(), String> {
+        let mut timer = Timer::new();
+        timer.set_tac(0x05);
+        timer.set_tima(0xff);
+        timer.tick(16);
+        timer.set_tima(0x10);
+        assert!(!timer.tick(4));
+        assert_eq!(timer.get_tima(), 0x10);
+        Ok(())
+    }
+
+    #[test]
+    fn test_div_reset_falling_edge() -> Result<(), String> {
+        let mut timer = Timer::new();
+        timer.set_tac(0x05);
+        timer.tick(8);
+        timer.reset_div();
+        assert_eq!(timer.get_tima(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,73 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const BITS_PER_SAMPLE: u16 = 16;
+/// the format tag of uncompressed pcm
+const PCM: u16 = 1;
+
+/// the file for a single channel next to the mixed recording, channel 1 of music.wav is music.ch1.wav
+pub fn get_channel_path(path: &Path, channel: usize) -> PathBuf {
+    path.with_extension(format!("ch{}.wav", channel))
+}
+
+/// 16 bit pcm with the channels interleaved
+pub fn write(path: &Path, sample_rate: u32, channels: u16, samples: &[i16]) -> io::Result<()> {
+    fs::write(path, encode(sample_rate, channels, samples))
+}
+
+/// a RIFF header with a fmt and a data chunk followed by the little endian samples
+fn encode(sample_rate: u32, channels: u16, samples: &[i16]) -> Vec<u8> {
+    let block_align = channels * BITS_PER_SAMPLE / 8;
+    let data_size = (samples.len() * 2) as u32;
+    let mut data = Vec::with_capacity(44 + samples.len() * 2);
+    data.extend_from_slice(b"RIFF");
+    data.extend_from_slice(&(36 + data_size).to_le_bytes());
+    data.extend_from_slice(b"WAVE");
+
+    data.extend_from_slice(b"fmt ");
+    data.extend_from_slice(&16u32.to_le_bytes());
+    data.extend_from_slice(&PCM.to_le_bytes());
+    data.extend_from_slice(&channels.to_le_bytes());
+    data.extend_from_slice(&sample_rate.to_le_bytes());
+    data.extend_from_slice(&(sample_rate * u32::from(block_align)).to_le_bytes());
+    data.extend_from_slice(&block_align.to_le_bytes());
+    data.extend_from_slice(&BITS_PER_SAMPLE.to_le_bytes());
+
+    data.extend_from_slice(b"data");
+    data.extend_from_slice(&data_size.to_le_bytes());
+    for sample in samples {
+        data.extend_from_slice(&sample.to_le_bytes());
+    }
+    data
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() -> Result<(), String> {
+        let data = encode(48_000, 2, &[1, -1, 0x1234, 0]);
+        assert_eq!(data.len(), 44 + 8);
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(data[4..8], 44u32.to_le_bytes());
+        assert_eq!(&data[8..16], b"WAVEfmt ");
+        assert_eq!(data[22..24], 2u16.to_le_bytes());
+        assert_eq!(data[24..28], 48_000u32.to_le_bytes());
+        assert_eq!(data[28..32], 192_000u32.to_le_bytes());
+        assert_eq!(data[32..34], 4u16.to_le_bytes());
+        assert_eq!(data[34..36], 16u16.to_le_bytes());
+        assert_eq!(&data[36..40], b"data");
+        assert_eq!(data[40..44], 8u32.to_le_bytes());
+        assert_eq!(data[44..], [0x01, 0x00, 0xff, 0xff, 0x34, 0x12, 0x00, 0x00]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_channel_path() -> Result<(), String> {
+        assert_eq!(get_channel_path(Path::new("out/music.wav"), 3), PathBuf::from("out/music.ch3.wav"));
+        Ok(())
+    }
+}